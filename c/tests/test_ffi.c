@@ -0,0 +1,160 @@
+/*
+    multi_avl の C 向け関数のテスト
+    cargo test -p ffi_check でコンパイルして実行する
+    単体でも cc -Iinclude c/tests/test_ffi.c target/release/libmulti_avl.a -lpthread -ldl -lm で動かせる
+*/
+#include <stdio.h>
+#include <string.h>
+
+#include "multi_avl.h"
+
+static int failures = 0;
+
+#define CHECK(cond) do { \
+    if (!(cond)) { \
+        fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #cond); \
+        failures++; \
+    } \
+} while (0)
+
+static void test_i64(void) {
+    MultiAVLI64 *tree = multi_avl_i64_new();
+    int64_t values[] = { 5, -3, 8, 5, 1, 5, INT64_MIN, INT64_MAX };
+    size_t i;
+    int64_t v = 0;
+    size_t count = 0;
+
+    CHECK(multi_avl_i64_len(tree) == 0);
+    CHECK(!multi_avl_i64_min(tree, &v));
+    for (i = 0; i < sizeof(values) / sizeof(values[0]); i++) {
+        multi_avl_i64_insert(tree, values[i]);
+    }
+    CHECK(multi_avl_i64_insert(tree, 5) == 4);
+    CHECK(multi_avl_i64_len(tree) == 9);
+    CHECK(multi_avl_i64_count(tree, 5) == 4);
+    CHECK(multi_avl_i64_count(tree, 2) == 0);
+
+    CHECK(multi_avl_i64_min(tree, &v) && v == INT64_MIN);
+    CHECK(multi_avl_i64_max(tree, &v) && v == INT64_MAX);
+
+    CHECK(multi_avl_i64_erase(tree, 5));
+    CHECK(!multi_avl_i64_erase(tree, 2));
+    CHECK(multi_avl_i64_erase(tree, INT64_MAX));
+    CHECK(multi_avl_i64_count(tree, 5) == 3);
+
+    /* 2 以上の値を昇順にたどる */
+    {
+        MultiAVLI64Cursor *cursor = multi_avl_i64_lower_bound(tree, 2);
+        int64_t expected[] = { 5, 8 };
+        size_t expected_counts[] = { 3, 1 };
+        for (i = 0; i < 2; i++) {
+            CHECK(multi_avl_i64_cursor_next(cursor, &v, &count));
+            CHECK(v == expected[i] && count == expected_counts[i]);
+        }
+        CHECK(!multi_avl_i64_cursor_next(cursor, &v, &count));
+        multi_avl_i64_cursor_free(cursor);
+    }
+
+    /* 全体をたどると重複を含めて len 個になる カーソルは木より後に解放してもよい */
+    {
+        MultiAVLI64Cursor *cursor = multi_avl_i64_iter(tree);
+        size_t total = 0;
+        int64_t prev = INT64_MIN;
+        while (multi_avl_i64_cursor_next(cursor, &v, &count)) {
+            CHECK(prev <= v);
+            prev = v;
+            total += count;
+        }
+        CHECK(total == multi_avl_i64_len(tree));
+        multi_avl_i64_free(tree);
+        CHECK(!multi_avl_i64_cursor_next(cursor, NULL, NULL));
+        multi_avl_i64_cursor_free(cursor);
+    }
+
+    /* 範囲外の lower_bound は何も返さない */
+    tree = multi_avl_i64_new();
+    multi_avl_i64_insert(tree, 1);
+    {
+        MultiAVLI64Cursor *cursor = multi_avl_i64_lower_bound(tree, 2);
+        CHECK(!multi_avl_i64_cursor_next(cursor, &v, NULL));
+        multi_avl_i64_cursor_free(cursor);
+    }
+    multi_avl_i64_free(tree);
+    multi_avl_i64_free(NULL);
+}
+
+static int buf_equals(MultiAVLByteBuf buf, const char *s) {
+    return buf.len == strlen(s) && (buf.len == 0 || memcmp(buf.data, s, buf.len) == 0);
+}
+
+static void insert_str(MultiAVLBytes *tree, const char *s) {
+    multi_avl_bytes_insert(tree, (const uint8_t *)s, strlen(s));
+}
+
+static void test_bytes(void) {
+    MultiAVLBytes *tree = multi_avl_bytes_new();
+    MultiAVLByteBuf buf;
+    size_t count = 0;
+    const uint8_t binary[] = { 'b', 0, 'x' };
+
+    CHECK(!multi_avl_bytes_max(tree, &buf));
+    insert_str(tree, "pear");
+    insert_str(tree, "apple");
+    insert_str(tree, "pear");
+    insert_str(tree, "b");
+    multi_avl_bytes_insert(tree, binary, sizeof(binary));
+    /* 空のバイト列は data が NULL でもよい */
+    CHECK(multi_avl_bytes_insert(tree, NULL, 0) == 1);
+
+    CHECK(multi_avl_bytes_len(tree) == 6);
+    CHECK(multi_avl_bytes_count(tree, (const uint8_t *)"pear", 4) == 2);
+    CHECK(multi_avl_bytes_count(tree, binary, sizeof(binary)) == 1);
+    CHECK(multi_avl_bytes_count(tree, binary, 2) == 0);
+
+    CHECK(multi_avl_bytes_min(tree, &buf) && buf_equals(buf, ""));
+    multi_avl_byte_buf_free(buf);
+    CHECK(multi_avl_bytes_max(tree, &buf) && buf_equals(buf, "pear"));
+    multi_avl_byte_buf_free(buf);
+    CHECK(multi_avl_bytes_max(tree, NULL));
+
+    CHECK(multi_avl_bytes_erase(tree, NULL, 0));
+    CHECK(!multi_avl_bytes_erase(tree, (const uint8_t *)"plum", 4));
+
+    /* 辞書順で "b" 以上: "b", "b\0x", "pear" */
+    {
+        MultiAVLBytesCursor *cursor = multi_avl_bytes_lower_bound(tree, (const uint8_t *)"b", 1);
+        CHECK(multi_avl_bytes_cursor_next(cursor, &buf, &count));
+        CHECK(buf_equals(buf, "b") && count == 1);
+        multi_avl_byte_buf_free(buf);
+        CHECK(multi_avl_bytes_cursor_next(cursor, &buf, NULL));
+        CHECK(buf.len == sizeof(binary) && memcmp(buf.data, binary, sizeof(binary)) == 0);
+        multi_avl_byte_buf_free(buf);
+        CHECK(multi_avl_bytes_cursor_next(cursor, NULL, &count) && count == 2);
+        CHECK(!multi_avl_bytes_cursor_next(cursor, &buf, &count));
+        multi_avl_bytes_cursor_free(cursor);
+    }
+
+    {
+        MultiAVLBytesCursor *cursor = multi_avl_bytes_iter(tree);
+        CHECK(multi_avl_bytes_cursor_next(cursor, &buf, NULL) && buf_equals(buf, "apple"));
+        multi_avl_byte_buf_free(buf);
+        multi_avl_bytes_cursor_free(cursor);
+    }
+
+    multi_avl_bytes_free(tree);
+}
+
+int multi_avl_ffi_tests(void) {
+    failures = 0;
+    test_i64();
+    test_bytes();
+    return failures;
+}
+
+#ifndef MULTI_AVL_NO_MAIN
+int main(void) {
+    int result = multi_avl_ffi_tests();
+    printf("%s (%d failures)\n", result == 0 ? "ok" : "FAILED", result);
+    return result == 0 ? 0 : 1;
+}
+#endif
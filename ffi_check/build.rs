@@ -0,0 +1,29 @@
+use std::env;
+use std::path::PathBuf;
+
+/*
+    multi_avl の src/ffi.rs から C のヘッダを OUT_DIR に生成し、
+    それを使って C のテストプログラムを静的ライブラリにしてテストにリンクする
+    ソースのディレクトリには書き込まない
+*/
+fn main() {
+    for path in ["../src/ffi.rs", "../cbindgen.toml", "../c/tests/test_ffi.c"] {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("..");
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let header = out_dir.join("multi_avl.h");
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).unwrap();
+    cbindgen::generate_with_config(&crate_dir, config)
+        .expect("failed to generate multi_avl.h")
+        .write_to_file(&header);
+    println!("cargo:rustc-env=MULTI_AVL_GENERATED_HEADER={}", header.display());
+
+    cc::Build::new()
+        .file("../c/tests/test_ffi.c")
+        .include(&out_dir)
+        .define("MULTI_AVL_NO_MAIN", None)
+        .warnings_into_errors(true)
+        .compile("test_ffi");
+}
@@ -0,0 +1,30 @@
+use core::ffi::c_int;
+
+// C のテストプログラムが呼ぶ関数を含めるために、使っていなくてもリンクさせる
+extern crate multi_avl;
+
+extern "C" {
+    // c/tests/test_ffi.c 失敗した確認の数を返す
+    fn multi_avl_ffi_tests() -> c_int;
+}
+
+pub fn run_c_tests() -> c_int {
+    unsafe { multi_avl_ffi_tests() }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_c_program() {
+        assert_eq!(super::run_c_tests(), 0);
+    }
+
+    // 配布するヘッダが src/ffi.rs から生成したものと同じか
+    #[test]
+    fn test_header_up_to_date() {
+        let generated_path = env!("MULTI_AVL_GENERATED_HEADER");
+        let generated = std::fs::read_to_string(generated_path).unwrap();
+        let committed = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/../include/multi_avl.h")).unwrap();
+        assert!(generated == committed, "include/multi_avl.h is out of date; copy {} over it", generated_path);
+    }
+}
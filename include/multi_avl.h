@@ -0,0 +1,87 @@
+#ifndef MULTI_AVL_H
+#define MULTI_AVL_H
+
+/* このファイルは src/ffi.rs から cbindgen で生成する 直接編集せず、cargo test -p ffi_check が出力するパスからコピーすること */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef struct MultiAVLBytes MultiAVLBytes;
+
+typedef struct MultiAVLBytesCursor MultiAVLBytesCursor;
+
+typedef struct MultiAVLI64 MultiAVLI64;
+
+typedef struct MultiAVLI64Cursor MultiAVLI64Cursor;
+
+typedef struct MultiAVLByteBuf {
+  uint8_t *data;
+  size_t len;
+} MultiAVLByteBuf;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+struct MultiAVLI64 *multi_avl_i64_new(void);
+
+void multi_avl_i64_free(struct MultiAVLI64 *tree);
+
+size_t multi_avl_i64_insert(struct MultiAVLI64 *tree, int64_t value);
+
+bool multi_avl_i64_erase(struct MultiAVLI64 *tree, int64_t value);
+
+size_t multi_avl_i64_count(const struct MultiAVLI64 *tree, int64_t value);
+
+size_t multi_avl_i64_len(const struct MultiAVLI64 *tree);
+
+bool multi_avl_i64_min(const struct MultiAVLI64 *tree, int64_t *out);
+
+bool multi_avl_i64_max(const struct MultiAVLI64 *tree, int64_t *out);
+
+struct MultiAVLI64Cursor *multi_avl_i64_iter(const struct MultiAVLI64 *tree);
+
+struct MultiAVLI64Cursor *multi_avl_i64_lower_bound(const struct MultiAVLI64 *tree, int64_t value);
+
+bool multi_avl_i64_cursor_next(struct MultiAVLI64Cursor *cursor, int64_t *out, size_t *count);
+
+void multi_avl_i64_cursor_free(struct MultiAVLI64Cursor *cursor);
+
+struct MultiAVLBytes *multi_avl_bytes_new(void);
+
+void multi_avl_bytes_free(struct MultiAVLBytes *tree);
+
+size_t multi_avl_bytes_insert(struct MultiAVLBytes *tree, const uint8_t *data, size_t len);
+
+bool multi_avl_bytes_erase(struct MultiAVLBytes *tree, const uint8_t *data, size_t len);
+
+size_t multi_avl_bytes_count(const struct MultiAVLBytes *tree, const uint8_t *data, size_t len);
+
+size_t multi_avl_bytes_len(const struct MultiAVLBytes *tree);
+
+bool multi_avl_bytes_min(const struct MultiAVLBytes *tree, struct MultiAVLByteBuf *out);
+
+bool multi_avl_bytes_max(const struct MultiAVLBytes *tree, struct MultiAVLByteBuf *out);
+
+struct MultiAVLBytesCursor *multi_avl_bytes_iter(const struct MultiAVLBytes *tree);
+
+struct MultiAVLBytesCursor *multi_avl_bytes_lower_bound(const struct MultiAVLBytes *tree,
+                                                        const uint8_t *data,
+                                                        size_t len);
+
+bool multi_avl_bytes_cursor_next(struct MultiAVLBytesCursor *cursor,
+                                 struct MultiAVLByteBuf *out,
+                                 size_t *count);
+
+void multi_avl_bytes_cursor_free(struct MultiAVLBytesCursor *cursor);
+
+void multi_avl_byte_buf_free(struct MultiAVLByteBuf buf);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* MULTI_AVL_H */
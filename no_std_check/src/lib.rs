@@ -0,0 +1,42 @@
+#![no_std]
+
+extern crate alloc;
+
+#[cfg(test)]
+extern crate std;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use multi_avl::avl::MultiAVL;
+
+// std に依存せずに主な操作ができることを確認する
+pub fn exercise() -> (Vec<(u32, usize)>, String) {
+    let mut tree = MultiAVL::new();
+    for i in [5u32, 3, 8, 3, 1, 9, 5] {
+        tree.insert(i);
+    }
+    tree.insert_n(4, 3);
+    tree.erase(&9);
+
+    let checkpoint = tree.checkpoint();
+    tree.erase_n(&4, 2);
+    tree.rollback(checkpoint);
+
+    tree.start_trace();
+    tree.insert(2);
+    let trace = multi_avl::avl::to_json_lines(&tree.stop_trace());
+
+    assert!(tree.validate().is_ok());
+    assert_eq!(tree.floor(&7), Some(5));
+    (tree.iter_counts().collect(), trace)
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_exercise() {
+        let (counts, trace) = super::exercise();
+        assert_eq!(counts, [(1, 1), (2, 1), (3, 2), (4, 3), (5, 2), (8, 1)]);
+        assert!(trace.starts_with("{\"event\":\"link\""));
+    }
+}
@@ -0,0 +1,124 @@
+# multi_avl の Python バインディングのテスト
+# pytest で実行するか、python test_multi_avl.py で直接実行する (pytest がなくても動く)
+import contextlib
+
+try:
+    import pytest
+except ImportError:
+    pytest = None
+
+from multi_avl import MultiAVL
+
+
+@contextlib.contextmanager
+def raises(exception):
+    if pytest is not None:
+        with pytest.raises(exception):
+            yield
+        return
+    try:
+        yield
+    except exception:
+        return
+    raise AssertionError(f"{exception.__name__} was not raised")
+
+
+def test_add_remove_count():
+    s = MultiAVL()
+    assert len(s) == 0
+    assert s.add(3) == 1
+    assert s.add(1) == 1
+    assert s.add(3) == 2
+    assert len(s) == 3
+    assert s.distinct_len() == 2
+    assert s.count(3) == 2
+    assert s.count(2) == 0
+    assert 1 in s
+    assert 2 not in s
+
+    s.remove(3)
+    assert s.count(3) == 1
+    assert s.discard(3)
+    assert not s.discard(3)
+    with raises(KeyError):
+        s.remove(3)
+    assert list(s) == [1]
+
+
+def test_iter_sorted_with_duplicates():
+    values = [5, 1, 4, 1, 5, 9, 2, 6, 5, 3]
+    s = MultiAVL(values)
+    assert list(s) == sorted(values)
+    assert repr(MultiAVL([2, 1, 2])) == "MultiAVL([1, 2, 2])"
+
+
+def test_kth_and_rank():
+    values = [5, 1, 4, 1, 5, 9, 2, 6, 5, 3]
+    s = MultiAVL(values)
+    expected = sorted(values)
+    for i, v in enumerate(expected):
+        assert s.kth(i) == v
+        assert s.kth(i - len(expected)) == v
+    with raises(IndexError):
+        s.kth(len(expected))
+    with raises(IndexError):
+        s.kth(-len(expected) - 1)
+    for v in range(0, 11):
+        assert s.rank(v) == sum(1 for w in expected if w < v)
+
+
+def test_bounds():
+    s = MultiAVL([10, 20, 20, 30])
+    assert s.min() == 10
+    assert s.max() == 30
+    assert s.lower_bound(20) == 20
+    assert s.lower_bound(21) == 30
+    assert s.lower_bound(31) is None
+    assert s.upper_bound(20) == 30
+    assert s.upper_bound(30) is None
+    assert s.floor(19) == 10
+    assert s.floor(9) is None
+    assert MultiAVL().min() is None
+
+
+def test_python_ordering():
+    # Python の比較で並ぶので、文字列やタプル、int と float の混在も扱える
+    s = MultiAVL(["pear", "apple", "fig"])
+    assert list(s) == ["apple", "fig", "pear"]
+    t = MultiAVL([(1, "b"), (1, "a"), (0, "z")])
+    assert t.kth(0) == (0, "z")
+    n = MultiAVL([1, 2.5, 2])
+    assert list(n) == [1, 2, 2.5]
+    assert n.count(2.0) == 1
+
+
+def test_incomparable_raises_and_keeps_tree():
+    s = MultiAVL([1, 2, 3])
+    with raises(TypeError):
+        s.add("x")
+    with raises(TypeError):
+        "x" in s
+    assert list(s) == [1, 2, 3]
+    s.add(4)
+    assert list(s) == [1, 2, 3, 4]
+
+
+def test_modify_during_iteration():
+    s = MultiAVL([1, 2, 3])
+    it = iter(s)
+    assert next(it) == 1
+    s.add(0)
+    with raises(RuntimeError):
+        next(it)
+
+
+if __name__ == "__main__":
+    import sys
+
+    if pytest is not None:
+        sys.exit(pytest.main([__file__]))
+    tests = [f for name, f in sorted(globals().items()) if name.startswith("test_")]
+    for test in tests:
+        test()
+        print(f"{test.__name__} ... ok")
+    print(f"{len(tests)} passed")
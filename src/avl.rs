@@ -1,22 +1,106 @@
-use std::rc::{Weak, Rc};
-use std::cell::RefCell;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::borrow;
+use core::cmp::Ordering;
+use core::marker::PhantomData;
+use core::ops::{Bound, RangeBounds};
+use core::sync::atomic::{self, AtomicUsize};
+
+mod balance;
+mod bounded;
+mod build;
+mod distance;
+mod dump;
+mod entry;
+mod error;
+mod interval;
+mod stats;
+mod update;
+mod weighted;
+mod sync;
+#[cfg(any(test, feature = "debug-validate"))]
+mod validate;
+#[cfg(feature = "paranoid")]
+mod paranoid;
+#[cfg(feature = "trace")]
+mod trace;
+#[cfg(feature = "rayon")]
+mod parallel;
+#[cfg(feature = "rank")]
+mod rank;
+#[cfg(feature = "rand")]
+mod sample;
+#[cfg(feature = "std")]
+mod snapshot;
+#[cfg(feature = "std")]
+mod durable;
+pub use balance::{BalancePolicy, Avl, RedBlack};
+pub use bounded::{BoundedMultiAVL, Keep};
+pub use distance::Distance;
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use error::{CountOverflow, IterError};
+pub use interval::IntervalMultiAVL;
+pub use stats::Stats;
+pub use weighted::WeightedMultiAVL;
+#[cfg(feature = "rayon")]
+pub use parallel::MultiAVLTreeParCountIter;
+#[cfg(feature = "std")]
+pub use snapshot::{SnapshotCodec, SnapshotError};
+#[cfg(feature = "std")]
+pub use durable::DurableMultiAVL;
+#[cfg(all(test, feature = "std"))]
+pub(crate) use snapshot::fix_checksum;
+use stats::Counters;
+use sync::{Rc, RefCell, Weak};
+#[cfg(any(test, feature = "debug-validate"))]
+pub use validate::ValidationError;
+#[cfg(feature = "trace")]
+pub use trace::{TraceEvent, TraceSide, DoubleRotationKind, to_json_lines};
 
 type NodeRef<T> = Rc<RefCell<Node<T>>>;
 
+// metaの下位2ビットはBalancePolicyが使い(AVL木ならバランス係数)、上位30ビットが個数
+const BALANCE_BITS: u32 = 2;
+const BALANCE_MASK: u32 = (1 << BALANCE_BITS) - 1;
+// 個数がこの値以上になったらOverflowCountsに本当の個数を持つ
+const COUNT_OVERFLOW: u32 = u32::MAX >> BALANCE_BITS;
+
 struct Node<T: Clone> {
     data: T,
-    height: i32,
-    counter: usize,
+    meta: u32,
     left: Option<NodeRef<T>>,
     right: Option<NodeRef<T>>,
     parent: Option<Weak<RefCell<Node<T>>>>,
+    // 部分木の要素数(重複を含む)とノード数 rank機能で順位やサンプリングに使う
+    #[cfg(feature = "rank")]
+    subtree_len: usize,
+    #[cfg(feature = "rank")]
+    subtree_distinct: usize,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 enum NodeSide {
     Left,
     Right
 }
 
+impl NodeSide {
+    fn opposite(self) -> NodeSide {
+        match self {
+            NodeSide::Left => NodeSide::Right,
+            NodeSide::Right => NodeSide::Left,
+        }
+    }
+}
+
+// 新しいノードを挿入する位置
+struct InsertPosition<T: Clone> {
+    parent: Option<NodeRef<T>>,
+    side: NodeSide,
+    is_min: bool,
+    is_max: bool,
+}
+
 impl<T: Clone> Node<T> {
     fn new(data: T, parent: Option<Weak<RefCell<Node<T>>>>) -> Node<T> {
         Self {  
@@ -24,35 +108,47 @@ impl<T: Clone> Node<T> {
             left: None,
             right: None,
             parent,
-            height: 0,
-            counter: 1,
+            meta: 1 << BALANCE_BITS,
+            #[cfg(feature = "rank")]
+            subtree_len: 0,
+            #[cfg(feature = "rank")]
+            subtree_distinct: 0,
         }
     }
 
-    fn adjust_height(&mut self) {
-        let left_height = match &self.left {
-            Some(v) => v.borrow().height + 1,
-            None => 0,
-        };
-        let right_height = match &self.right {
-            Some(v) => v.borrow().height + 1,
-            None => 0,
-        };
+    fn balance_bits(&self) -> u32 {
+        self.meta & BALANCE_MASK
+    }
 
-        self.height = left_height.max(right_height);
+    fn set_balance_bits(&mut self, bits: u32) {
+        debug_assert!(bits <= BALANCE_MASK);
+        self.meta = (self.meta & !BALANCE_MASK) | bits;
     }
 
-    fn get_balance_factor(&self) -> i32 {
-        let left_height = match &self.left {
-            Some(v) => v.borrow().height + 1,
-            None => 0,
-        };
-        let right_height = match &self.right {
-            Some(v) => v.borrow().height + 1,
-            None => 0,
-        };
+    fn small_count(&self) -> u32 {
+        self.meta >> BALANCE_BITS
+    }
+
+    fn set_small_count(&mut self, count: u32) {
+        debug_assert!(count <= COUNT_OVERFLOW);
+        self.meta = (count << BALANCE_BITS) | (self.meta & BALANCE_MASK);
+    }
 
-        left_height - right_height
+    // 部分木をすべてたどって高さを求める 葉は0
+    // 木の形の出力でしか使わない 検証はdfs_validate_balanceで１回たどるだけで高さも求める
+    fn height(&self) -> i32 {
+        [&self.left, &self.right].into_iter()
+            .flatten()
+            .map(|v| v.borrow().height() + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Borrowを通した比較用のキー
+    fn key<Q>(&self) -> &Q
+        where T: borrow::Borrow<Q>, Q: ?Sized
+    {
+        borrow::Borrow::borrow(&self.data)
     }
 
     fn count_children(&self) -> usize {
@@ -68,22 +164,117 @@ impl<T: Clone> Node<T> {
     }
 }
 
-pub struct MultiAVL<T>
-    where T: Ord + Clone
+/*
+    30ビットに収まらない個数 ノードのアドレスをキーにする
+    イテレータからも読むので木とRcで共有する
+    表にあるのは、木の中にあって個数がCOUNT_OVERFLOW以上のノードだけ
+    ノードは個数を1にしてから木から外す(decrease_count)ので、外れたときに表からも消える
+    木の中のノードは生きているのでアドレスは重ならず、解放された場所が再利用されても古い個数を読むことはない
+    外れたノードを持ち続けるイテレータは小さい個数を読むので、表は見ない
+*/
+#[derive(Default)]
+struct OverflowCounts {
+    counts: RefCell<BTreeMap<usize, usize>>,
+}
+
+impl OverflowCounts {
+    fn address<T: Clone>(node: &NodeRef<T>) -> usize {
+        Rc::as_ptr(node) as usize
+    }
+
+    fn get<T: Clone>(&self, node: &NodeRef<T>) -> usize {
+        let count = node.borrow().small_count();
+        if count < COUNT_OVERFLOW {
+            return count as usize;
+        }
+        self.counts.borrow()[&Self::address(node)]
+    }
+
+    fn set<T: Clone>(&self, node: &NodeRef<T>, count: usize) {
+        let was_overflow = node.borrow().small_count() == COUNT_OVERFLOW;
+        if count < COUNT_OVERFLOW as usize {
+            node.borrow_mut().set_small_count(count as u32);
+            if was_overflow {
+                self.counts.borrow_mut().remove(&Self::address(node));
+            }
+        } else {
+            node.borrow_mut().set_small_count(COUNT_OVERFLOW);
+            self.counts.borrow_mut().insert(Self::address(node), count);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.counts.borrow().len()
+    }
+}
+
+// rollbackのために記録する操作
+enum Operation<T> {
+    Insert(T, usize),
+    Erase(T, usize),
+}
+
+// checkpoint以降の操作ログ
+// marksは有効なcheckpointのidとその時点でのログの長さ
+struct Journal<T> {
+    operations: Vec<Operation<T>>,
+    marks: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    id: usize,
+}
+
+// checkpointのidはすべての木で重ならないように振るので、他の木のcheckpointは見つからずに無視される
+static NEXT_CHECKPOINT_ID: AtomicUsize = AtomicUsize::new(0);
+
+pub struct MultiAVL<T, P = Avl>
+    where T: Ord + Clone, P: BalancePolicy
 {
     root: Option<NodeRef<T>>,
     size: usize,
+    distinct: usize,
     min_node: Option<NodeRef<T>>,
     max_node: Option<NodeRef<T>>,
+    journal: Option<Journal<T>>,
+    #[cfg(feature = "trace")]
+    trace: Option<Vec<TraceEvent<T>>>,
+    counters: Counters,
+    overflow: Rc<OverflowCounts>,
+    // 部分木から求める値をノードに持たせるとき、(ノード, 個数)からその値を計算し直す関数
+    augment: Option<fn(&NodeRef<T>, usize)>,
+    policy: PhantomData<fn() -> P>,
+}
+
+impl<T: Ord + Clone, P: BalancePolicy> Default for MultiAVL<T, P> {
+    fn default() -> Self {
+        Self::with_policy()
+    }
 }
 
 impl<T: Ord + Clone> MultiAVL<T> {
     pub fn new() -> MultiAVL<T> {
+        Self::with_policy()
+    }
+}
+
+impl<T: Ord + Clone, P: BalancePolicy> MultiAVL<T, P> {
+    // 木の形を保つ方法を型で指定して作る MultiAVL::<i32, RedBlack>::with_policy()
+    pub fn with_policy() -> MultiAVL<T, P> {
         Self { 
             root: None, 
             size: 0,
+            distinct: 0,
             min_node: None,
             max_node: None,
+            journal: None,
+            #[cfg(feature = "trace")]
+            trace: None,
+            counters: Counters::default(),
+            overflow: Rc::default(),
+            augment: None,
+            policy: PhantomData,
         }
     }
 
@@ -95,7 +286,71 @@ impl<T: Ord + Clone> MultiAVL<T> {
         self.size == 0
     }
 
-    pub fn insert(&mut self, value: T) {
+    // 挿入後のvalueの個数を返す 要素数がusizeに収まらなければpanicする
+    pub fn insert(&mut self, value: T) -> usize {
+        match self.try_insert(value) {
+            Ok(count) => count,
+            Err(_) => panic!("insert: element count overflow"),
+        }
+    }
+
+    // 要素数がusizeに収まらなければ、木を変えずに値を返す
+    pub fn try_insert(&mut self, value: T) -> Result<usize, CountOverflow<T>> {
+        if !self.can_insert(1) {
+            return Err( CountOverflow { value } );
+        }
+        self.record(|| Operation::Insert(value.clone(), 1));
+
+        let node = match self.locate(&value) {
+            Ok(node) => {
+                self.increase_count(&node, 1);
+                node
+            },
+            Err(position) => self.insert_at(position, value, 1),
+        };
+        Ok( self.overflow.get(&node) )
+    }
+
+    // 挿入後のvalueの個数を返す 要素数がusizeに収まらなければpanicする
+    pub fn insert_n(&mut self, value: T, n: usize) -> usize {
+        match self.try_insert_n(value, n) {
+            Ok(count) => count,
+            Err(_) => panic!("insert: element count overflow"),
+        }
+    }
+
+    // 挿入後のvalueの個数を返す 要素数がusizeに収まらなければ、木を変えずに値を返す
+    pub fn try_insert_n(&mut self, value: T, n: usize) -> Result<usize, CountOverflow<T>> {
+        if !self.can_insert(n) {
+            return Err( CountOverflow { value } );
+        }
+        let count = match self.entry(value) {
+            Entry::Occupied(mut v) => {
+                v.insert_n(n);
+                v.count()
+            },
+            Entry::Vacant(v) => {
+                v.insert_n(n);
+                n
+            },
+        };
+        Ok( count )
+    }
+
+    // あとn個入れても要素数がusizeに収まるか ノードの個数は要素数以下なので、これも収まる
+    fn can_insert(&self, n: usize) -> bool {
+        self.size.checked_add(n).is_some()
+    }
+
+    pub fn entry(&mut self, value: T) -> Entry<'_, T, P> {
+        match self.locate(&value) {
+            Ok(node) => Entry::Occupied( OccupiedEntry { tree: self, node } ),
+            Err(position) => Entry::Vacant( VacantEntry { tree: self, value, position } ),
+        }
+    }
+
+    // valueのノードを探す 無ければ挿入すべき位置を返す
+    fn locate(&self, value: &T) -> Result<NodeRef<T>, InsertPosition<T>> {
         let mut parent = None;
         let mut node = self.root.as_ref().map(Rc::clone);
         let mut side = NodeSide::Left;
@@ -103,50 +358,212 @@ impl<T: Ord + Clone> MultiAVL<T> {
         let mut is_max = true;
         let mut is_min = true;
 
+        self.counters.count_search();
         while let Some(n) = node {
-            parent = Some( Rc::clone(&n) );
-            if value == n.borrow().data {
-                n.borrow_mut().counter += 1;
-                self.size += 1;
-                return;
-            } else if value < n.borrow().data {
-                is_max = false;
-                side = NodeSide::Left;
-                node = n.borrow().left.as_ref().map(Rc::clone);
-            } else {
-                is_min = false;
-                side = NodeSide::Right;
-                node = n.borrow().right.as_ref().map(Rc::clone);
+            self.counters.count_comparison();
+            let ordering = value.cmp(&n.borrow().data);
+            match ordering {
+                Ordering::Equal => return Ok(n),
+                Ordering::Less => {
+                    is_max = false;
+                    side = NodeSide::Left;
+                    node = n.borrow().left.as_ref().map(Rc::clone);
+                },
+                Ordering::Greater => {
+                    is_min = false;
+                    side = NodeSide::Right;
+                    node = n.borrow().right.as_ref().map(Rc::clone);
+                },
             }
+            parent = Some( n );
         }
-        self.size += 1;
 
+        Err( InsertPosition { parent, side, is_min, is_max } )
+    }
+
+    // locateで得た位置に個数countのノードを作る
+    fn insert_at(&mut self, position: InsertPosition<T>, value: T, count: usize) -> NodeRef<T> {
         let new_node = Rc::new(RefCell::new( Node::new( value, None )));
+        self.insert_node_at(position, new_node, count)
+    }
 
-        if is_max {
+    // どこにも繋がっていないノードをlocateで得た位置に入れる
+    fn insert_node_at(&mut self, position: InsertPosition<T>, new_node: NodeRef<T>, count: usize) -> NodeRef<T> {
+        self.counters.count_insert();
+        self.size = self.size.checked_add(count).expect("insert: element count overflow");
+        self.distinct += 1;
+        self.overflow.set(&new_node, count);
+
+        if position.is_max {
             self.max_node = Some( Rc::clone(&new_node) );
         }
-        if is_min {
+        if position.is_min {
             self.min_node = Some( Rc::clone(&new_node) );
         }
 
-        if let Some(v) = &parent {
-            match side {
-                NodeSide::Left => Self::link_left_node(&v, &new_node),
-                NodeSide::Right => Self::link_right_node(&v, &new_node),
+        if let Some(v) = &position.parent {
+            match position.side {
+                NodeSide::Left => self.link_left_node(v, &new_node),
+                NodeSide::Right => self.link_right_node(v, &new_node),
             }
-            self.rebalance( Rc::clone(v) );
         } else {
-            self.root = Some( new_node );
+            self.set_root( Some( Rc::clone(&new_node) ) );
+        }
+        self.augment_to_root(&new_node);
+        P::after_insert(self, &new_node);
+
+        #[cfg(feature = "paranoid")]
+        self.paranoid_check("insert");
+        new_node
+    }
+
+    // ノードの個数を増やす 木の形は変わらない
+    fn increase_count(&mut self, node: &NodeRef<T>, count: usize) {
+        self.counters.count_insert();
+        let (Some(counter), Some(size)) = (self.overflow.get(node).checked_add(count), self.size.checked_add(count)) else {
+            panic!("insert: element count overflow");
+        };
+        self.overflow.set(node, counter);
+        self.size = size;
+        self.augment_to_root(node);
+
+        #[cfg(feature = "paranoid")]
+        self.paranoid_check("increase count");
+    }
+
+    // ノードの個数を減らし、0になったらノードを削除する
+    fn decrease_count(&mut self, node: &NodeRef<T>, count: usize) {
+        self.counters.count_erase();
+        let counter = self.overflow.get(node);
+        debug_assert!(count <= counter);
+        if count < counter {
+            self.overflow.set(node, counter - count);
+            self.size -= count;
+            self.augment_to_root(node);
+        } else {
+            // erase_nodeで最後の１つが消される
+            self.overflow.set(node, 1);
+            self.size -= counter - 1;
+            self.erase_node(node);
+        }
+
+        #[cfg(feature = "paranoid")]
+        self.paranoid_check("erase");
+    }
+
+    // value未満の最大の値
+    pub fn predecessor<Q>(&self, value: &Q) -> Option<T>
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        let (lower, _) = self.find_neighbor_nodes(value, false);
+        lower.map(|v| v.borrow().data.clone())
+    }
+
+    // valueより大きい最小の値
+    pub fn successor<Q>(&self, value: &Q) -> Option<T>
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        let (_, upper) = self.find_neighbor_nodes(value, false);
+        upper.map(|v| v.borrow().data.clone())
+    }
+
+    // value以下の最大の値
+    pub fn floor<Q>(&self, value: &Q) -> Option<T>
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        let (lower, _) = self.find_neighbor_nodes(value, true);
+        lower.map(|v| v.borrow().data.clone())
+    }
+
+    // value以上の最小の値
+    pub fn ceil<Q>(&self, value: &Q) -> Option<T>
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        let (_, upper) = self.find_neighbor_nodes(value, true);
+        upper.map(|v| v.borrow().data.clone())
+    }
+
+    // valueに最も近い値 距離が等しければ小さい方を返す
+    pub fn nearest(&self, value: &T) -> Option<T>
+        where T: Distance
+    {
+        let (lower, upper) = self.find_neighbor_nodes(value, true);
+        match (lower, upper) {
+            (Some(l), Some(u)) => {
+                let l = l.borrow().data.clone();
+                let u = u.borrow().data.clone();
+                if value.distance(&u) < value.distance(&l) {
+                    Some(u)
+                } else {
+                    Some(l)
+                }
+            },
+            (Some(v), None) | (None, Some(v)) => Some( v.borrow().data.clone() ),
+            (None, None) => None,
         }
-        
     }
 
     pub fn iter(&self) -> MultiAVLTreeIter<T>{
         if let Some(v) = self.min_iter() {
             return v;
         }
-        MultiAVLTreeIter { now: None, counter: 0 }
+        MultiAVLTreeIter { now: None, counter: 0, overflow: Rc::clone(&self.overflow) }
+    }
+
+    pub fn iter_counts(&self) -> MultiAVLTreeCountIter<T> {
+        MultiAVLTreeCountIter {
+            front: self.min_node.as_ref().map(Rc::downgrade),
+            back: self.max_node.as_ref().map(Rc::downgrade),
+            overflow: Rc::clone(&self.overflow),
+        }
+    }
+
+    // 昇順に(値, 個数)を渡す iter_countsと違って値を複製しない
+    pub fn for_each_count<F: FnMut(&T, usize)>(&self, mut f: F) {
+        let _ = self.try_for_each_count(|value, count| {
+            f(value, count);
+            Ok::<(), core::convert::Infallible>(())
+        });
+    }
+
+    // fがエラーを返したらそこで止めてそのエラーを返す
+    pub fn try_for_each_count<E, F: FnMut(&T, usize) -> Result<(), E>>(&self, mut f: F) -> Result<(), E> {
+        let mut node = self.min_node.as_ref().map(Rc::clone);
+        while let Some(n) = node {
+            f(&n.borrow().data, self.overflow.get(&n))?;
+            node = next_node(&n);
+        }
+        Ok(())
+    }
+
+    // 範囲内の値とその個数を返す
+    pub fn range_counts<Q, R>(&self, range: R) -> MultiAVLTreeCountIter<T>
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized, R: RangeBounds<Q>
+    {
+        let front = match range.start_bound() {
+            Bound::Included(v) => self.find_neighbor_nodes(v, true).1,
+            Bound::Excluded(v) => self.find_neighbor_nodes(v, false).1,
+            Bound::Unbounded => self.min_node.as_ref().map(Rc::clone),
+        };
+        let back = match range.end_bound() {
+            Bound::Included(v) => self.find_neighbor_nodes(v, true).0,
+            Bound::Excluded(v) => self.find_neighbor_nodes(v, false).0,
+            Bound::Unbounded => self.max_node.as_ref().map(Rc::clone),
+        };
+
+        match (front, back) {
+            (Some(f), Some(b)) if f.borrow().data <= b.borrow().data => MultiAVLTreeCountIter {
+                front: Some( Rc::downgrade(&f) ),
+                back: Some( Rc::downgrade(&b) ),
+                overflow: Rc::clone(&self.overflow),
+            },
+            _ => MultiAVLTreeCountIter { front: None, back: None, overflow: Rc::clone(&self.overflow) },
+        }
+    }
+
+    // 重複を含まない要素数
+    pub fn distinct_len(&self) -> usize {
+        self.distinct
     }
 
     pub fn max_value(&self) -> Option<T> {
@@ -160,7 +577,7 @@ impl<T: Ord + Clone> MultiAVL<T> {
     pub fn max_iter(&self) -> Option<MultiAVLTreeIter<T>> {
         if let Some(v) = &self.max_node {
             let v = Rc::clone(v);
-            return Some( Self::node_to_iter(&v) );
+            return Some( self.node_to_iter(&v) );
         }
         None
     }
@@ -176,43 +593,141 @@ impl<T: Ord + Clone> MultiAVL<T> {
     pub fn min_iter(&self) -> Option<MultiAVLTreeIter<T>> {
         if let Some(v) = &self.min_node {
             let v = Rc::clone(v);
-            return Some( Self::node_to_iter(&v) );
+            return Some( self.node_to_iter(&v) );
         }
         None
     }
 
-    pub fn contains(&self, value: T) -> bool {
-        let node = self.find_node(&value);
+    pub fn contains<Q>(&self, value: &Q) -> bool
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        let node = self.find_node(value);
         node.is_some()
     }
 
-    pub fn erase(&mut self, value: T) {
-        let node = self.find_node(&value);
-        if node.is_none() {
+    pub fn count<Q>(&self, value: &Q) -> usize
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        match self.find_node(value) {
+            Some(v) => self.overflow.get(&v),
+            None => 0,
+        }
+    }
+
+    // valueを１つ削除する なければfalseを返す
+    pub fn erase<Q>(&mut self, value: &Q) -> bool
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        let node = match self.find_node(value) {
+            Some(v) => v,
+            None => return false,
+        };
+        self.record(|| Operation::Erase(node.borrow().data.clone(), 1));
+        self.decrease_count(&node, 1);
+        true
+    }
+
+    // 最大n個を削除し、実際に削除した個数を返す
+    pub fn erase_n<Q>(&mut self, value: &Q, n: usize) -> usize
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        let node = match self.find_node(value) {
+            Some(v) => v,
+            None => return 0,
+        };
+        OccupiedEntry { tree: self, node }.remove_n(n)
+    }
+
+    // iterが指している要素を１つ削除し、その値を返す
+    pub fn erase_iter(&mut self, iter: MultiAVLTreeIter<T>) -> Result<T, IterError> {
+        let node = iter.now.ok_or(IterError::Exhausted)?;
+        let node = Weak::upgrade(&node).ok_or(IterError::Dangling)?;
+        let value = node.borrow().data.clone();
+        self.record(|| Operation::Erase(value.clone(), 1));
+        self.decrease_count(&node, 1);
+        Ok( value )
+    }
+
+    // 現在の状態を記録し、以降の insert / erase をログに残す
+    pub fn checkpoint(&mut self) -> Checkpoint {
+        let id = NEXT_CHECKPOINT_ID.fetch_add(1, atomic::Ordering::Relaxed);
+
+        let journal = self.journal.get_or_insert_with(|| Journal { operations: Vec::new(), marks: Vec::new() });
+        journal.marks.push( (id, journal.operations.len()) );
+        Checkpoint { id }
+    }
+
+    // checkpoint以降の操作をログから逆順に打ち消す checkpoint自体は引き続き有効
+    // 既に無効になったcheckpointや他の木のcheckpointは無視する
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        let position = match self.find_mark(&checkpoint) {
+            Some(v) => v,
+            None => return,
+        };
+
+        // 巻き戻し中の操作を記録しないようにログを外しておく
+        let mut journal = self.journal.take().unwrap();
+        while journal.operations.len() > position {
+            match journal.operations.pop().unwrap() {
+                Operation::Insert(value, count) => {
+                    let node = self.find_node(&value).unwrap();
+                    self.decrease_count(&node, count);
+                },
+                Operation::Erase(value, count) => match self.locate(&value) {
+                    Ok(node) => self.increase_count(&node, count),
+                    Err(position) => { self.insert_at(position, value, count); },
+                },
+            }
+        }
+        // 後から作られたcheckpointは無効にする
+        journal.marks.retain(|(id, _)| *id <= checkpoint.id);
+        self.journal = Some( journal );
+    }
+
+    // checkpoint以降の操作を確定させ、checkpointとそれ以降のcheckpointを無効にする
+    // 最も外側のcheckpointであればログの記録を止める
+    pub fn commit(&mut self, checkpoint: Checkpoint) {
+        if self.find_mark(&checkpoint).is_none() {
             return;
         }
-        self.erase_node(&node.unwrap());
+
+        let journal = self.journal.as_mut().unwrap();
+        journal.marks.retain(|(id, _)| *id < checkpoint.id);
+        if journal.marks.is_empty() {
+            self.journal = None;
+        }
+    }
+
+    fn find_mark(&self, checkpoint: &Checkpoint) -> Option<usize> {
+        let journal = self.journal.as_ref()?;
+        journal.marks.iter().find(|(id, _)| *id == checkpoint.id).map(|(_, position)| *position)
     }
 
-    pub fn erase_iter(&mut self, iter: MultiAVLTreeIter<T>) {
-        if let Some(node) = iter.now {
-            let node = Weak::upgrade(&node);
-            if node.is_none() { return; }
-            let node = node.unwrap();
-            self.erase_node(&node)
+    fn record<F: FnOnce() -> Operation<T>>(&mut self, operation: F) {
+        if let Some(journal) = &mut self.journal {
+            journal.operations.push(operation());
         }
     }
 
-    fn find_node(&self, value: &T) -> Option<NodeRef<T>> {
+    fn find_node<Q>(&self, value: &Q) -> Option<NodeRef<T>>
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        self.find_node_by(value, borrow::Borrow::borrow)
+    }
+
+    // ノードの値からkeyで取り出したキーでvalueを探す 値を包んだ型の木で中の値のBorrowを使って探すのに使う
+    fn find_node_by<Q, F>(&self, value: &Q, key: F) -> Option<NodeRef<T>>
+        where Q: Ord + ?Sized, F: Fn(&T) -> &Q
+    {
         let mut node = self.root.as_ref().map(Rc::clone);
+        self.counters.count_search();
         while let Some(n) = node.clone() {
+            self.counters.count_comparison();
             let n_borrow = n.borrow();
-            if *value == n_borrow.data {
-                break;
-            } else if *value < n_borrow.data {
-                node = n_borrow.left.as_ref().map(Rc::clone);
-            } else {
-                node = n_borrow.right.as_ref().map(Rc::clone);
+            match value.cmp(key(&n_borrow.data)) {
+                Ordering::Equal => break,
+                Ordering::Less => node = n_borrow.left.as_ref().map(Rc::clone),
+                Ordering::Greater => node = n_borrow.right.as_ref().map(Rc::clone),
             }
         }
         
@@ -220,64 +735,131 @@ impl<T: Ord + Clone> MultiAVL<T> {
     }
 
     fn find_max_node(&self) -> Option<NodeRef<T>> {
-        if self.root.is_none() {
-            return None;
-        }
-        let mut node = self.root.as_ref().map(Rc::clone).unwrap();
-        while let Some(n) = &node.clone().borrow().right {
-            node = Rc::clone(&n);
-        }
-        Some(node)
+        Some( Self::subtree_max_node(self.root.as_ref()?) )
     }
 
     fn find_min_node(&self) -> Option<NodeRef<T>> {
-        if self.root.is_none() {
-            return None;
+        Some( Self::subtree_min_node(self.root.as_ref()?) )
+    }
+
+    // nodeを根とする部分木の最大ノード
+    fn subtree_max_node(node: &NodeRef<T>) -> NodeRef<T> {
+        let mut node = Rc::clone(node);
+        while let Some(n) = &node.clone().borrow().right {
+            node = Rc::clone(n);
         }
-        let mut node = self.root.as_ref().map(Rc::clone).unwrap();
+        node
+    }
+
+    // nodeを根とする部分木の最小ノード
+    fn subtree_min_node(node: &NodeRef<T>) -> NodeRef<T> {
+        let mut node = Rc::clone(node);
         while let Some(n) = &node.clone().borrow().left {
-            node = Rc::clone(&n);
+            node = Rc::clone(n);
         }
-        Some(node)
+        node
     }
 
-    fn node_to_iter(node: &NodeRef<T>) -> MultiAVLTreeIter<T> {
-        MultiAVLTreeIter { now: Some( Rc::downgrade(&node) ), counter: 1 }
+    /*
+        valueの前後にあるノードを１回の探索で求める
+        inclusiveならvalueと等しいノードを両方の答えとする
+        (value未満(以下)の最大ノード, valueより大きい(以上の)最小ノード)
+    */
+    fn find_neighbor_nodes<Q>(&self, value: &Q, inclusive: bool) -> (Option<NodeRef<T>>, Option<NodeRef<T>>)
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        let mut lower = None;
+        let mut upper = None;
+        let mut node = self.root.as_ref().map(Rc::clone);
+        self.counters.count_search();
+        while let Some(n) = node {
+            self.counters.count_comparison();
+            let n_borrow = n.borrow();
+            match value.cmp(n_borrow.key()) {
+                Ordering::Less => {
+                    upper = Some( Rc::clone(&n) );
+                    node = n_borrow.left.as_ref().map(Rc::clone);
+                },
+                Ordering::Greater => {
+                    lower = Some( Rc::clone(&n) );
+                    node = n_borrow.right.as_ref().map(Rc::clone);
+                },
+                Ordering::Equal if inclusive => {
+                    return ( Some( Rc::clone(&n) ), Some( Rc::clone(&n) ) );
+                },
+                Ordering::Equal => {
+                    // 等しいノードの左部分木の最大と右部分木の最小が答え
+                    if let Some(v) = &n_borrow.left {
+                        lower = Some( Self::subtree_max_node(v) );
+                    }
+                    if let Some(v) = &n_borrow.right {
+                        upper = Some( Self::subtree_min_node(v) );
+                    }
+                    break;
+                },
+            }
+        }
+
+        (lower, upper)
     }
 
-    fn remove_node(side: NodeSide, node: &NodeRef<T>) -> Option<NodeRef<T>> {
+    fn node_to_iter(&self, node: &NodeRef<T>) -> MultiAVLTreeIter<T> {
+        MultiAVLTreeIter { now: Some( Rc::downgrade(node) ), counter: 1, overflow: Rc::clone(&self.overflow) }
+    }
+
+    fn remove_node(&mut self, side: NodeSide, node: &NodeRef<T>) -> Option<NodeRef<T>> {
         let retu = match side {
             NodeSide::Left  => node.borrow_mut().left.take(),
             NodeSide::Right => node.borrow_mut().right.take()
         };
-        Self::adjust_height(&node);
+
+        #[cfg(feature = "trace")]
+        if let Some(child) = &retu {
+            self.record_trace(|| TraceEvent::Unlink {
+                parent: node.borrow().data.clone(),
+                child: child.borrow().data.clone(),
+                side: (&side).into(),
+            });
+        }
         retu
     }
 
-    fn remove_left(node: &NodeRef<T>) -> Option<NodeRef<T>> {
-        Self::remove_node(NodeSide::Left, node)
+    fn remove_left(&mut self, node: &NodeRef<T>) -> Option<NodeRef<T>> {
+        self.remove_node(NodeSide::Left, node)
     }
 
-    fn remove_right(node: &NodeRef<T>) -> Option<NodeRef<T>> {
-        Self::remove_node(NodeSide::Right, node)
+    fn remove_right(&mut self, node: &NodeRef<T>) -> Option<NodeRef<T>> {
+        self.remove_node(NodeSide::Right, node)
     }
 
-    fn link_node(side: NodeSide, parent: &NodeRef<T>, child: &NodeRef<T>) {
-        child.borrow_mut().parent = Some( Rc::downgrade(&parent) );
+    fn link_node(&mut self, side: NodeSide, parent: &NodeRef<T>, child: &NodeRef<T>) {
+        #[cfg(feature = "trace")]
+        self.record_trace(|| TraceEvent::Link {
+            parent: parent.borrow().data.clone(),
+            child: child.borrow().data.clone(),
+            side: (&side).into(),
+        });
+
+        child.borrow_mut().parent = Some( Rc::downgrade(parent) );
         match side {
             NodeSide::Left  => parent.borrow_mut().left = Some( Rc::clone(child) ),
             NodeSide::Right => parent.borrow_mut().right = Some( Rc::clone(child) )
         }
+    }
 
-        Self::adjust_height(&parent);
+    fn set_root(&mut self, node: Option<NodeRef<T>>) {
+        #[cfg(feature = "trace")]
+        self.record_trace(|| TraceEvent::Root { node: node.as_ref().map(|v| v.borrow().data.clone()) });
+
+        self.root = node;
     }
 
-    fn link_right_node(parent: &NodeRef<T>, child: &NodeRef<T>) {
-        Self::link_node(NodeSide::Right, parent, child);
+    fn link_right_node(&mut self, parent: &NodeRef<T>, child: &NodeRef<T>) {
+        self.link_node(NodeSide::Right, parent, child);
     }
 
-    fn link_left_node(parent: &NodeRef<T>, child: &NodeRef<T>) {
-        Self::link_node(NodeSide::Left, parent, child);
+    fn link_left_node(&mut self, parent: &NodeRef<T>, child: &NodeRef<T>) {
+        self.link_node(NodeSide::Left, parent, child);
     }
 
     fn is_max_node(&self, node: &NodeRef<T>) -> bool {
@@ -294,11 +876,14 @@ impl<T: Ord + Clone> MultiAVL<T> {
         false
     }
 
-    fn erase_node(&mut self, node: &NodeRef<T>) {
-        node.borrow_mut().counter -= 1;
-        if node.borrow().counter > 0 {
+    // 個数を１つ減らす ノードが木から外れたら、外れたノードを返す
+    fn erase_node(&mut self, node: &NodeRef<T>) -> Option<NodeRef<T>> {
+        let counter = self.overflow.get(node);
+        if counter > 1 {
+            self.overflow.set(node, counter - 1);
             self.size -= 1;
-            return;
+            self.augment_to_root(node);
+            return None;
         }
 
         //　最大最小を計算しなおすべきかどうか
@@ -312,15 +897,12 @@ impl<T: Ord + Clone> MultiAVL<T> {
         }
 
         let num_child = node.borrow().count_children();
-        match num_child {
+        let removed = match num_child {
             0 => self.erase_node_no_child(node),
             1 => self.erase_node_one_child(node),
-            2 => { 
-                node.borrow_mut().counter += 1;
-                self.erase_node_two_children(node)
-            },
+            2 => self.erase_node_two_children(node),
             _ => panic!("Unexpected number of children"),
-        }
+        };
 
         if recalc_max {
             self.max_node = self.find_max_node();
@@ -328,6 +910,7 @@ impl<T: Ord + Clone> MultiAVL<T> {
         if recalc_min {
             self.min_node = self.find_min_node();
         }
+        Some( removed )
     }
 
     // nodeが親のどちらについているかを返す 根ノードの場合Noneが返る
@@ -336,458 +919,371 @@ impl<T: Ord + Clone> MultiAVL<T> {
             let parent = parent.upgrade().unwrap();
 
             if let Some(parent_left) = &parent.borrow().left {
-                if Rc::ptr_eq(&node, parent_left) {
+                if Rc::ptr_eq(node, parent_left) {
                     return Some( NodeSide::Left );
                 }
             }
-            debug_assert!(Rc::ptr_eq(&parent.borrow().right.as_ref().unwrap(), node));
-            return Some( NodeSide::Right );
+            debug_assert!(Rc::ptr_eq(parent.borrow().right.as_ref().unwrap(), node));
+            Some( NodeSide::Right )
         } else {
             None
         }
     }
 
     // 子を持たないノードの削除
-    fn erase_node_no_child(&mut self, target: &NodeRef<T>) {
+    fn erase_node_no_child(&mut self, target: &NodeRef<T>) -> NodeRef<T> {
         debug_assert_eq!(target.borrow().count_children(), 0);
-        if let Some(parent) = &target.borrow().parent {
-            let parent = parent.upgrade().unwrap();
-
-            // 親のどちらにいるかを取得
-            let side = Self::get_node_position(&target).unwrap();
-            match side {
-                NodeSide::Left  => { Self::remove_left(&parent); },
-                NodeSide::Right => { Self::remove_right(&parent); }
-            }
+        let position = Self::parent_and_side(target);
+        match &position {
+            Some((parent, NodeSide::Left)) => { self.remove_left(parent); },
+            Some((parent, NodeSide::Right)) => { self.remove_right(parent); },
+            None => self.set_root(None),
+        }
 
-            self.rebalance(parent);
-        } else {
-            self.root = None;
+        if let Some((parent, _)) = &position {
+            self.augment_to_root(parent);
         }
+        P::after_erase(self, target, position);
         self.size -= 1;
+        self.distinct -= 1;
+        Rc::clone(target)
     }
 
     // 子を１つだけ持つノードの削除
-    fn erase_node_one_child(&mut self, target: &NodeRef<T>) {
+    fn erase_node_one_child(&mut self, target: &NodeRef<T>) -> NodeRef<T> {
         debug_assert_eq!(target.borrow().count_children(), 1);
 
-        let child;
-        if target.borrow().left.is_some() {
-            child = Self::remove_left(&target).unwrap();
+        let child = if target.borrow().left.is_some() {
+            self.remove_left(target).unwrap()
         } else {
-            child = Self::remove_right(&target).unwrap();
-        }
-
-        if let Some(parent) = &target.borrow().parent {
-            let parent = Weak::upgrade(parent).unwrap();
+            self.remove_right(target).unwrap()
+        };
 
-            let side: NodeSide = Self::get_node_position(target).unwrap();
-            match side {
-                NodeSide::Left => {
-                    Self::link_left_node(&parent, &child);
-                },
-                NodeSide::Right => {
-                    Self::link_right_node(&parent, &child);
-                }
-            }
+        let position = Self::parent_and_side(target);
+        match &position {
+            Some((parent, NodeSide::Left)) => self.link_left_node(parent, &child),
+            Some((parent, NodeSide::Right)) => self.link_right_node(parent, &child),
+            None => {
+                child.borrow_mut().parent = None;
+                self.set_root( Some( Rc::clone(&child) ) );
+            },
+        }
 
-            self.rebalance(parent);
-        } else {
-            child.borrow_mut().parent = None;
-            self.root = Some( Rc::clone(&child) );
-        };
+        if let Some((parent, _)) = &position {
+            self.augment_to_root(parent);
+        }
+        P::after_erase(self, target, position);
         self.size -= 1;
+        self.distinct -= 1;
+        Rc::clone(target)
     }
 
     // 子を２つもつノードの削除
-    fn erase_node_two_children(&mut self, node: &NodeRef<T>) {
+    // 左部分木の最大ノードと位置を入れ替えてから外す 値はノードから動かさないので、
+    // 消した値を指すイテレータはDanglingになり、他の値を指すイテレータはそのまま使える
+    fn erase_node_two_children(&mut self, node: &NodeRef<T>) -> NodeRef<T> {
         debug_assert_eq!(node.borrow().count_children(), 2);
 
         let left_node = node.borrow().left.as_ref().map(Rc::clone).unwrap();
         // 左の部分木から最大ノードを探す
         let mut max_node = left_node;
         while let Some(v) = &Rc::clone(&max_node).borrow().right {
-            max_node = Rc::clone(&v);
+            max_node = Rc::clone(v);
         }
 
-        // 削除対象ノードと最大ノードのデータを入れ替える
-        std::mem::swap(&mut node.borrow_mut().data, &mut max_node.borrow_mut().data);
-        std::mem::swap(&mut node.borrow_mut().counter, &mut max_node.borrow_mut().counter);
+        #[cfg(feature = "trace")]
+        self.record_trace(|| TraceEvent::Swap {
+            node: node.borrow().data.clone(),
+            predecessor: max_node.borrow().data.clone(),
+        });
+        self.swap_positions(node, &max_node);
 
-        // 最大ノードだったノードを消す
-        debug_assert!(max_node.borrow().right.is_none());
-        self.erase_node(&max_node);
+        // 最大ノードがあった位置に移ったノードを消す
+        debug_assert!(node.borrow().right.is_none());
+        self.erase_node(node).unwrap()
     }
 
-    // ノードの高さを計算しなおす
-    fn adjust_height(node: &NodeRef<T>) {
-        let left_height = match &node.borrow().left {
-            Some(v) => v.borrow().height + 1, 
-            None => 0,
-        };
-        let right_height = match &node.borrow().right {
-            Some(v) => v.borrow().height + 1,
-            None => 0,
+    // nodeとその左部分木の最大ノードの位置を入れ替える バランスのビットは位置に付いたまま残す
+    // 部分木から求める値は、この後の削除で根までたどるときに計算し直される
+    fn swap_positions(&mut self, node: &NodeRef<T>, max_node: &NodeRef<T>) {
+        let position = Self::parent_and_side(node);
+        let left = node.borrow_mut().left.take().unwrap();
+        let right = node.borrow_mut().right.take().unwrap();
+        let max_left = max_node.borrow_mut().left.take();
+        let max_parent = Weak::upgrade(max_node.borrow().parent.as_ref().unwrap()).unwrap();
+
+        // nodeを最大ノードがあった位置に置く
+        if let Some(v) = &max_left {
+            v.borrow_mut().parent = Some( Rc::downgrade(node) );
+        }
+        node.borrow_mut().left = max_left;
+        let new_left = if Rc::ptr_eq(&max_parent, node) {
+            Rc::clone(node)
+        } else {
+            node.borrow_mut().parent = Some( Rc::downgrade(&max_parent) );
+            max_parent.borrow_mut().right = Some( Rc::clone(node) );
+            left
         };
 
-        node.borrow_mut().height = left_height.max(right_height);
+        // 最大ノードをnodeがあった位置に置く
+        new_left.borrow_mut().parent = Some( Rc::downgrade(max_node) );
+        right.borrow_mut().parent = Some( Rc::downgrade(max_node) );
+        {
+            let mut max_borrow = max_node.borrow_mut();
+            max_borrow.left = Some( new_left );
+            max_borrow.right = Some( right );
+        }
+        match position {
+            Some((parent, side)) => {
+                max_node.borrow_mut().parent = Some( Rc::downgrade(&parent) );
+                match side {
+                    NodeSide::Left => parent.borrow_mut().left = Some( Rc::clone(max_node) ),
+                    NodeSide::Right => parent.borrow_mut().right = Some( Rc::clone(max_node) ),
+                }
+            },
+            None => {
+                max_node.borrow_mut().parent = None;
+                self.root = Some( Rc::clone(max_node) );
+            },
+        }
+
+        let node_bits = node.borrow().balance_bits();
+        let max_bits = max_node.borrow().balance_bits();
+        node.borrow_mut().set_balance_bits(max_bits);
+        max_node.borrow_mut().set_balance_bits(node_bits);
     }
 
-    // nodeを根として左回転
+    // nodeを根として左回転 木の形だけを変え、バランス係数は呼び出し側で直す
     fn rotate_left(&mut self, node: &NodeRef<T>) {
-        let right_child = Self::remove_right(&node);
-        if right_child.is_none() {
+        if node.borrow().right.is_none() {
             return;
         }
-        let right_child = right_child.unwrap();
-        
+        #[cfg(feature = "trace")]
+        self.record_trace(|| TraceEvent::RotateLeft { node: node.borrow().data.clone() });
+        self.counters.count_rotation();
+
+        let right_child = self.remove_right(node).unwrap();
+
         // ノードの付け替え
-        if let Some(left_node) = &Self::remove_left(&right_child) {
-            Self::link_right_node(&node, &left_node);
+        if let Some(left_node) = &self.remove_left(&right_child) {
+            self.link_right_node(node, left_node);
         }
 
         match &node.borrow().parent {
             Some(v) => {
-                let v = Weak::upgrade(&v).unwrap();
-                if v.borrow().left.is_some() && Rc::ptr_eq(&node, v.borrow().left.as_ref().unwrap()) {
-                    Self::link_left_node(&v, &right_child);
+                let v = Weak::upgrade(v).unwrap();
+                if v.borrow().left.is_some() && Rc::ptr_eq(node, v.borrow().left.as_ref().unwrap()) {
+                    self.link_left_node(&v, &right_child);
                 } else {
-                    Self::link_right_node(&v, &right_child);
+                    self.link_right_node(&v, &right_child);
                 }
             },
             None => {
-                self.root = Some( Rc::clone(&right_child) );
+                self.set_root( Some( Rc::clone(&right_child) ) );
                 right_child.borrow_mut().parent = None;
             }
         }
 
-        Self::link_left_node(&right_child, &node);
+        self.link_left_node(&right_child, node);
+        self.augment_node(node);
+        self.augment_node(&right_child);
 
-        // 高さ調節
-        Self::adjust_height(&node);
-        Self::adjust_height(&right_child);
+        #[cfg(feature = "paranoid")]
+        self.paranoid_check_rotation();
     }
 
-    // nodeを根として右回転
+    // nodeを根として右回転 木の形だけを変え、バランス係数は呼び出し側で直す
     fn rotate_right(&mut self, node: &NodeRef<T>) {
-        let left_child = Self::remove_left(&node);
-        if left_child.is_none() {
+        if node.borrow().left.is_none() {
             return;
         }
-        let left_child = left_child.unwrap();
+        #[cfg(feature = "trace")]
+        self.record_trace(|| TraceEvent::RotateRight { node: node.borrow().data.clone() });
+        self.counters.count_rotation();
+
+        let left_child = self.remove_left(node).unwrap();
 
         //　ノードの付け替え
-        if let Some(right_node) = &Self::remove_right(&left_child) {
-            Self::link_left_node(&node, right_node);
+        if let Some(right_node) = &self.remove_right(&left_child) {
+            self.link_left_node(node, right_node);
         }
 
         match &node.borrow().parent {
             Some(v) => {
                 let v = Weak::upgrade(v).unwrap();
-                if v.borrow().left.is_some() && Rc::ptr_eq(&node, v.borrow().left.as_ref().unwrap()) {
-                    Self::link_left_node(&v, &left_child);
+                if v.borrow().left.is_some() && Rc::ptr_eq(node, v.borrow().left.as_ref().unwrap()) {
+                    self.link_left_node(&v, &left_child);
                 } else {
-                    Self::link_right_node(&v, &left_child);
+                    self.link_right_node(&v, &left_child);
                 }
             },
             None => {
-                self.root = Some( Rc::clone(&left_child) );
+                self.set_root( Some( Rc::clone(&left_child) ) );
                 left_child.borrow_mut().parent = None;
             }
         }
 
-        Self::link_right_node(&left_child, &node);
+        self.link_right_node(&left_child, node);
+        self.augment_node(node);
+        self.augment_node(&left_child);
 
-        //　高さ調整
-        Self::adjust_height(&node);
-        Self::adjust_height(&left_child);
+        #[cfg(feature = "paranoid")]
+        self.paranoid_check_rotation();
     }
 
-    // 二重回転が必要かどうか
-    fn need_double_rot(node: &NodeRef<T>) -> bool {
-        let n_balance = node.borrow().get_balance_factor();
-        if n_balance == 2 {
-            let mut c_balance = 0;
-            if let Some(v) = &node.borrow().left {
-                c_balance = v.borrow().get_balance_factor();
-            }
-
-            if c_balance == -1 {
-                return true;
-            }
-        }
-        if n_balance == -2 {
-            let mut c_balance = 0;
-            if let Some(v) = &node.borrow().right {
-                c_balance = v.borrow().get_balance_factor();
-            }
+    // 部分木から求める値を持たせる 回転や挿入・削除、個数の増減のたびに、部分木が変わったノードでfが呼ばれる
+    fn set_augment(&mut self, f: fn(&NodeRef<T>, usize)) {
+        debug_assert!(self.root.is_none());
+        self.augment = Some( f );
+    }
 
-            if c_balance == 1 {
-                return true;
-            }
+    fn augment_node(&self, node: &NodeRef<T>) {
+        #[cfg(feature = "rank")]
+        rank::update_subtree_counts(node, &self.overflow);
+        if let Some(f) = self.augment {
+            f(node, self.overflow.get(node));
         }
-
-        false
     }
 
-    // nodeをリバランスする
-    fn rebalance_node(&mut self, node: NodeRef<T>){
-        node.borrow_mut().adjust_height();
-        let balance = node.borrow().get_balance_factor();
-        if balance == 2 {
-            if Self::need_double_rot(&node) {
-                let left_child = Rc::clone( &node.borrow().left.as_ref().unwrap() );
-                self.rotate_left(&left_child);
-            }
-            self.rotate_right(&node);
-        }else if balance == -2 {
-            if Self::need_double_rot(&node) {
-                let right_child = Rc::clone( &node.borrow().right.as_ref().unwrap() );
-                self.rotate_right(&right_child);
-            }
-            self.rotate_left(&node);
+    // 平衡化の前に、変わったノードから根までを計算し直す 回転はその後も値を保つ
+    fn augment_to_root(&self, node: &NodeRef<T>) {
+        // rank機能では部分木の要素数をいつも持つ
+        #[cfg(not(feature = "rank"))]
+        if self.augment.is_none() {
+            return;
+        }
+        let mut now = Some( Rc::clone(node) );
+        while let Some(v) = now {
+            self.augment_node(&v);
+            now = v.borrow().parent.as_ref().and_then(Weak::upgrade);
         }
     }
 
-    // nodeから上に根に向かってリバランスしていく
-    fn rebalance(&mut self, node: NodeRef<T>) {
-        let mut now = node;
-        loop {
-            let mut nxt = None;
-            if let Some(v) = &now.borrow().parent {
-                nxt = Some( Weak::upgrade(v).unwrap() );
-            }
-            Self::adjust_height(&now);
-            self.rebalance_node(now);
-            if let Some(v) = &nxt {
-                now = Rc::clone(v);
-            } else {
-                break;
-            }
-        }
+    // nodeの親とnodeがどちらの子かを返す
+    fn parent_and_side(node: &NodeRef<T>) -> Option<(NodeRef<T>, NodeSide)> {
+        let side = Self::get_node_position(node)?;
+        let parent = Weak::upgrade(node.borrow().parent.as_ref().unwrap()).unwrap();
+        Some( (parent, side) )
     }
 }
 
 pub struct MultiAVLTreeIter<T: Clone> {
     now: Option<Weak<RefCell<Node<T>>>>,
     counter: usize,
+    overflow: Rc<OverflowCounts>,
 }
 
 impl<T: Clone> Iterator for MultiAVLTreeIter<T> {
     type Item = T;
     fn next(&mut self) -> Option<Self::Item> {
-        if self.now.is_none(){ return None;}
-
-        let node = Weak::upgrade(&self.now.as_ref().clone().unwrap());
-        if node.is_none() {
-            return  None;
-        }
-
-        
-        let node = node.unwrap();
+        let node = Weak::upgrade(self.now.as_ref()?)?;
         self.counter += 1;
-        if self.counter <= node.borrow().counter {
+        if self.counter <= self.overflow.get(&node) {
             return Some( node.borrow().data.clone() );
         } 
         self.counter = 1;
 
         let ret_data = node.borrow().data.clone();
-        if let Some(v) = &node.clone().borrow().right {
-            // 今のノードに右の子があるなら、右の子から可能な限り左に行く
-            let mut now = Rc::clone(v);
-            while let Some(nxt) = &now.clone().borrow().left {
-                now = Rc::clone(nxt);    
-            }
-
-            self.now = Some( Rc::downgrade(&now) );
-        } else {
-            // 親の左の子になるまでたどる
-            self.now = None;
-            let mut now =  Rc::clone(&node);
-            while let Some(parent) = &now.clone().borrow().parent {
-                let parent = Weak::upgrade(parent);
-                if parent.is_none() { return None; }
-                let parent = parent.unwrap();
-
-                // 左の子か確認 左の子であれば終わり
-                if let Some(left) = &parent.clone().borrow().left {
-                    if Rc::ptr_eq(left, &now) {
-                        self.now = Some( Rc::downgrade(&parent) );
-                        break;
-                    }
-                }
-
-                now = parent;
-            }
-        }
+        self.now = next_node(&node).map(|v| Rc::downgrade(&v));
         Some( ret_data )
     }
 }
 
-// テスト用関数
-#[cfg(test)]
-impl MultiAVL<i32> {
-    pub fn check_consistent(&self) -> Result<(),Box<dyn std::error::Error>> {
-        // ノードの親子関係をかくにんするものを作る
-        self.is_size_correct()?;
-        self.is_order_correct()?;
-        self.is_relation_correct()?;
-        self.is_node_height_correct()?;
-        self.is_balanced()?;
-        Ok(())
-    }
-
-    /*
-        木の大小関係を確認する
-        left < node < right
-    */
-    fn is_order_correct(&self) -> Result<(),Box<dyn std::error::Error>>{
-        if let Some(v) = &self.root {
-            Self::dfs_is_order_correct(v, None, None)?;
-        }
-        Ok(())
-    }
-    
-    fn dfs_is_order_correct(node: &NodeRef<i32>, min_value: Option<i32>, max_value: Option<i32>) -> Result<(),Box<dyn std::error::Error>>{
-        if let Some(max) = max_value {
-            if node.borrow().data > max {
-                return Err("order is not correct".into());
-            }
-        }
-
-        if let Some(min) = min_value {
-            if node.borrow().data < min {
-                return Err("order is not correct".into());
-            }
-        }
-
-        if let Some(left) = &node.borrow().left {
-            Self::dfs_is_order_correct(left, min_value, Some( node.borrow().data ))?;
-        }
-        if let Some(right) = &node.borrow().right {
-           Self::dfs_is_order_correct(right, Some( node.borrow().data ), max_value)?;
-        }
-
-        Ok(())
-    }
+// 値とその個数を１ノードずつ返すイテレータ front から back までの範囲を両端から進める
+pub struct MultiAVLTreeCountIter<T: Clone> {
+    front: Option<Weak<RefCell<Node<T>>>>,
+    back: Option<Weak<RefCell<Node<T>>>>,
+    overflow: Rc<OverflowCounts>,
+}
 
-    // 木の要素数を確認する
-    fn is_size_correct(&self) -> Result<(),Box<dyn std::error::Error>> {
-        let mut cnt = 0;
-        if let Some(v) = &self.root {
-            cnt = Self::dfs_size_correct(v)
-        }
-        if self.size == cnt {
-            Ok(())
+impl<T: Clone> MultiAVLTreeCountIter<T> {
+    // frontとbackが出会ったら終わり
+    fn advance(&mut self, node: &NodeRef<T>, next: Option<NodeRef<T>>, is_front: bool) -> (T, usize) {
+        let is_last = match (&self.front, &self.back) {
+            (Some(f), Some(b)) => Weak::ptr_eq(f, b),
+            _ => true,
+        };
+        if is_last {
+            self.front = None;
+            self.back = None;
+        } else if is_front {
+            self.front = next.map(|v| Rc::downgrade(&v));
         } else {
-            Err("size is not correct".into())
+            self.back = next.map(|v| Rc::downgrade(&v));
         }
+        ( node.borrow().data.clone(), self.overflow.get(node) )
     }
+}
 
-    fn dfs_size_correct(node: &NodeRef<i32>) -> usize {
-        let mut cnt = node.borrow().counter;
-        if let Some(v) = &node.borrow().left {
-            cnt += Self::dfs_size_correct(v);
-        }
-        if let Some(v) = &node.borrow().right {
-            cnt += Self::dfs_size_correct(v);
-        }
-
-        cnt
+impl<T: Clone> Iterator for MultiAVLTreeCountIter<T> {
+    type Item = (T, usize);
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = Weak::upgrade(self.front.as_ref()?)?;
+        let next = next_node(&node);
+        Some( self.advance(&node, next, true) )
     }
+}
 
-    // 木の高さを確認する
-    fn is_node_height_correct(&self) -> Result<(),Box<dyn std::error::Error>> {
-        if let Some(v) = &self.root {
-            Self::dfs_is_node_height_correct(v)?;
-        }
-
-        Ok(())
+impl<T: Clone> DoubleEndedIterator for MultiAVLTreeCountIter<T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let node = Weak::upgrade(self.back.as_ref()?)?;
+        let prev = prev_node(&node);
+        Some( self.advance(&node, prev, false) )
     }
-    
-    fn dfs_is_node_height_correct(node: &NodeRef<i32>) -> Result<i32, Box<dyn std::error::Error>> {
-        let mut ans = 0;
-        if let Some(v) = &node.borrow().left {
-            let left_height = Self::dfs_is_node_height_correct(v)?;
-            ans = ans.max(1 + left_height);
-        }
-        if let Some(v) = &node.borrow().right {
-            let right_height = Self::dfs_is_node_height_correct(v)?;
-            ans = ans.max(1 + right_height);
-        }
+}
 
-        if node.borrow().height == ans {
-            Ok(ans)
-        } else {
-            Err("node height is not correct".into())
+// 中順で次のノードを返す
+fn next_node<T: Clone>(node: &NodeRef<T>) -> Option<NodeRef<T>> {
+    if let Some(v) = &node.borrow().right {
+        // 右の子があるなら、右の子から可能な限り左に行く
+        let mut now = Rc::clone(v);
+        while let Some(nxt) = &now.clone().borrow().left {
+            now = Rc::clone(nxt);    
         }
+        return Some( now );
     }
 
-    // ノードの親子関係を確認する
-    fn is_relation_correct(&self) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(v) = &self.root {
-            Self::dfs_is_relation_correct(v)?;
-            if v.borrow().parent.is_some() {
-                return Err("relation is not correct".into());
+    // 親の左の子になるまでたどる
+    let mut now = Rc::clone(node);
+    while let Some(parent) = &now.clone().borrow().parent {
+        let parent = Weak::upgrade(parent)?;
+
+        // 左の子か確認 左の子であれば終わり
+        if let Some(left) = &parent.borrow().left {
+            if Rc::ptr_eq(left, &now) {
+                return Some( Rc::clone(&parent) );
             }
         }
 
-        Ok(())
-    }
-
-    fn check_relation(node: &NodeRef<i32>, child: &NodeRef<i32>) -> bool {
-        let child_parent = &child.borrow().parent;
-        if child_parent.is_none() { return false; } // 親要素が設定されてるか
-        let child_parent = child_parent.as_ref().unwrap();
-
-        let child_parent = child_parent.upgrade();
-        if child_parent.is_none() { return false; } // 親要素が生きてるか
-
-        let child_parent = &child_parent.unwrap();
-        Rc::ptr_eq(node, &child_parent) // 参照が正しいか 
-
+        now = parent;
     }
+    None
+}
 
-    fn dfs_is_relation_correct(node: &NodeRef<i32>) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(left) = &node.borrow().left {
-            if !Self::check_relation(node, left) {
-                return Err("relation is not correct".into());
-            }
+// 中順で前のノードを返す
+fn prev_node<T: Clone>(node: &NodeRef<T>) -> Option<NodeRef<T>> {
+    if let Some(v) = &node.borrow().left {
+        // 左の子があるなら、左の子から可能な限り右に行く
+        let mut now = Rc::clone(v);
+        while let Some(nxt) = &now.clone().borrow().right {
+            now = Rc::clone(nxt);
         }
-        if let Some(right) = &node.borrow().right {
-            if !Self::check_relation(node, right) {
-                return Err("relation is not correct".into());
-            }
-        }
-
-        Ok(())
+        return Some( now );
     }
 
+    // 親の右の子になるまでたどる
+    let mut now = Rc::clone(node);
+    while let Some(parent) = &now.clone().borrow().parent {
+        let parent = Weak::upgrade(parent)?;
 
-    /*
-        木のバランスを確認する
-        -1 <= left.height - right.height <= 1
-    */
-    fn is_balanced(&self) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(v) = &self.root {
-            Self::dfs_is_balanced(v)?;
+        // 右の子か確認 右の子であれば終わり
+        if let Some(right) = &parent.borrow().right {
+            if Rc::ptr_eq(right, &now) {
+                return Some( Rc::clone(&parent) );
+            }
         }
-        
-        Ok(())
-    }
 
-    fn dfs_is_balanced(node: &NodeRef<i32>) -> Result<(), Box<dyn std::error::Error>> {
-        let balance = node.borrow().get_balance_factor();
-        
-        if balance < -1 || balance > 1 {
-            return Err("tree is not balanced".into());
-        }
-        if let Some(v) = &node.borrow().left {
-            Self::dfs_is_balanced(v)?
-        }
-        if let Some(v) = &node.borrow().right {
-            Self::dfs_is_balanced(v)?
-        }
-        Ok(())   
+        now = parent;
     }
+    None
 }
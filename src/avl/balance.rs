@@ -0,0 +1,46 @@
+// Rebalanceは外から名前を付けられないので、非公開のノード型を引数に取ってよい
+#![allow(private_interfaces)]
+
+use super::{MultiAVL, NodeRef, NodeSide};
+
+mod height;
+mod red_black;
+pub use height::Avl;
+pub use red_black::RedBlack;
+
+/*
+    木の形を保つ方法
+    回転やノードの付け替えはMultiAVLが持ち、どこで回転するかとノードの2ビットの使い方をここで決める
+    実装はこのクレートの中のものに限る
+*/
+pub trait BalancePolicy: Sized + sealed::Rebalance {}
+
+pub(super) mod sealed {
+    use alloc::string::String;
+
+    use super::{BalancePolicy, MultiAVL, NodeRef, NodeSide};
+    #[cfg(any(test, feature = "debug-validate"))]
+    use super::super::ValidationError;
+
+    pub trait Rebalance: Sized {
+        // nodeを新しく木に繋いだ後に呼ばれる
+        fn after_insert<T: Ord + Clone>(tree: &mut MultiAVL<T, Self>, node: &NodeRef<T>)
+            where Self: BalancePolicy;
+
+        // 子を１つ以下しか持たないremovedを外した後に呼ばれる
+        // positionはremovedがいた場所で、根だったならNone
+        fn after_erase<T: Ord + Clone>(tree: &mut MultiAVL<T, Self>, removed: &NodeRef<T>, position: Option<(NodeRef<T>, NodeSide)>)
+            where Self: BalancePolicy;
+
+        // 昇順の列の中央を根にして作った木で、ノードに持たせる2ビット
+        // 左右の部分木の大きさと、根を0とした深さ、木全体の大きさから決める
+        fn built_bits(left_len: usize, right_len: usize, depth: usize, len: usize) -> u32;
+
+        // 木の形を出力するときのノードの属性の名前と値
+        fn node_attribute<T: Clone>(node: &NodeRef<T>) -> (&'static str, String);
+
+        #[cfg(any(test, feature = "debug-validate"))]
+        fn validate_balance<T: Ord + Clone>(tree: &MultiAVL<T, Self>) -> Result<(), ValidationError<T>>
+            where Self: BalancePolicy;
+    }
+}
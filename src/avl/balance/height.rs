@@ -0,0 +1,224 @@
+use alloc::string::{String, ToString};
+
+use super::super::{MultiAVL, Node, NodeRef, NodeSide, Rc};
+#[cfg(feature = "trace")]
+use super::super::{TraceEvent, DoubleRotationKind};
+#[cfg(any(test, feature = "debug-validate"))]
+use super::super::ValidationError;
+use super::{BalancePolicy, sealed::Rebalance};
+
+// 左右の部分木の高さの差を1以下に保つAVL木 ノードの2ビットにバランス係数を持つ
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Avl;
+
+impl BalancePolicy for Avl {}
+
+impl Rebalance for Avl {
+    fn after_insert<T: Ord + Clone>(tree: &mut MultiAVL<T, Self>, node: &NodeRef<T>) {
+        if let Some((parent, side)) = MultiAVL::<T, Self>::parent_and_side(node) {
+            tree.retrace_insert(parent, side);
+        }
+    }
+
+    fn after_erase<T: Ord + Clone>(tree: &mut MultiAVL<T, Self>, _removed: &NodeRef<T>, position: Option<(NodeRef<T>, NodeSide)>) {
+        if let Some((parent, side)) = position {
+            tree.retrace_erase(parent, side);
+        }
+    }
+
+    fn built_bits(left_len: usize, right_len: usize, _depth: usize, _len: usize) -> u32 {
+        // 中央で分けて作った大きさmの木の高さはlog2(m)の切り捨て 空なら-1
+        let height = |len: usize| if len == 0 { -1 } else { len.ilog2() as i32 };
+        balance_bits(height(left_len) - height(right_len))
+    }
+
+    fn node_attribute<T: Clone>(node: &NodeRef<T>) -> (&'static str, String) {
+        ("balance", node.borrow().get_balance_factor().to_string())
+    }
+
+    #[cfg(any(test, feature = "debug-validate"))]
+    fn validate_balance<T: Ord + Clone>(tree: &MultiAVL<T, Self>) -> Result<(), ValidationError<T>> {
+        if let Some(v) = &tree.root {
+            MultiAVL::<T, Self>::dfs_validate_balance(v)?;
+        }
+        Ok(())
+    }
+}
+
+fn balance_bits(balance: i32) -> u32 {
+    debug_assert!((-1..=1).contains(&balance));
+    match balance {
+        0 => 0,
+        1 => 1,
+        _ => 2,
+    }
+}
+
+impl<T: Clone> Node<T> {
+    // 左の高さ - 右の高さ
+    pub(in crate::avl) fn get_balance_factor(&self) -> i32 {
+        match self.balance_bits() {
+            0 => 0,
+            1 => 1,
+            _ => -1,
+        }
+    }
+
+    pub(in crate::avl) fn set_balance_factor(&mut self, balance: i32) {
+        self.set_balance_bits(balance_bits(balance));
+    }
+}
+
+impl<T: Ord + Clone> MultiAVL<T, Avl> {
+    /*
+        バランス係数がbalance(±2)になったnodeを回転し、新しい部分木の根を返す
+        子のバランス係数が0になるのは削除のときだけで、そのときは部分木の高さが変わらない
+    */
+    fn rebalance_node(&mut self, node: &NodeRef<T>, balance: i32) -> NodeRef<T> {
+        let new_root;
+        if balance == 2 {
+            let left_child = Rc::clone( node.borrow().left.as_ref().unwrap() );
+            let child_balance = left_child.borrow().get_balance_factor();
+            if child_balance == -1 {
+                #[cfg(feature = "trace")]
+                self.record_trace(|| TraceEvent::DoubleRotation {
+                    node: node.borrow().data.clone(),
+                    kind: DoubleRotationKind::LeftRight,
+                });
+                self.counters.count_double_rotation();
+                let pivot = Rc::clone( left_child.borrow().right.as_ref().unwrap() );
+                let pivot_balance = pivot.borrow().get_balance_factor();
+                self.rotate_left(&left_child);
+                self.rotate_right(node);
+
+                node.borrow_mut().set_balance_factor(if pivot_balance == 1 { -1 } else { 0 });
+                left_child.borrow_mut().set_balance_factor(if pivot_balance == -1 { 1 } else { 0 });
+                pivot.borrow_mut().set_balance_factor(0);
+                new_root = pivot;
+            } else {
+                self.rotate_right(node);
+
+                node.borrow_mut().set_balance_factor(1 - child_balance);
+                left_child.borrow_mut().set_balance_factor(child_balance - 1);
+                new_root = left_child;
+            }
+        } else {
+            debug_assert_eq!(balance, -2);
+            let right_child = Rc::clone( node.borrow().right.as_ref().unwrap() );
+            let child_balance = right_child.borrow().get_balance_factor();
+            if child_balance == 1 {
+                #[cfg(feature = "trace")]
+                self.record_trace(|| TraceEvent::DoubleRotation {
+                    node: node.borrow().data.clone(),
+                    kind: DoubleRotationKind::RightLeft,
+                });
+                self.counters.count_double_rotation();
+                let pivot = Rc::clone( right_child.borrow().left.as_ref().unwrap() );
+                let pivot_balance = pivot.borrow().get_balance_factor();
+                self.rotate_right(&right_child);
+                self.rotate_left(node);
+
+                node.borrow_mut().set_balance_factor(if pivot_balance == -1 { 1 } else { 0 });
+                right_child.borrow_mut().set_balance_factor(if pivot_balance == 1 { -1 } else { 0 });
+                pivot.borrow_mut().set_balance_factor(0);
+                new_root = pivot;
+            } else {
+                self.rotate_left(node);
+
+                node.borrow_mut().set_balance_factor(-1 - child_balance);
+                right_child.borrow_mut().set_balance_factor(child_balance + 1);
+                new_root = right_child;
+            }
+        }
+
+        new_root
+    }
+
+    // nodeのside側の部分木の高さが1増えたので、根に向かってバランス係数を直す
+    fn retrace_insert(&mut self, node: NodeRef<T>, side: NodeSide) {
+        let mut now = node;
+        let mut side = side;
+        loop {
+            let balance = now.borrow().get_balance_factor() + match side {
+                NodeSide::Left => 1,
+                NodeSide::Right => -1,
+            };
+            if balance.abs() == 2 {
+                // 挿入では回転すると部分木の高さが挿入前に戻る
+                self.rebalance_node(&now, balance);
+                return;
+            }
+            now.borrow_mut().set_balance_factor(balance);
+            if balance == 0 {
+                return;
+            }
+
+            // nowの高さが1増えた
+            match Self::parent_and_side(&now) {
+                Some((parent, s)) => {
+                    now = parent;
+                    side = s;
+                },
+                None => return,
+            }
+        }
+    }
+
+    // nodeのside側の部分木の高さが1減ったので、根に向かってバランス係数を直す
+    fn retrace_erase(&mut self, node: NodeRef<T>, side: NodeSide) {
+        let mut now = node;
+        let mut side = side;
+        loop {
+            let balance = now.borrow().get_balance_factor() + match side {
+                NodeSide::Left => -1,
+                NodeSide::Right => 1,
+            };
+            if balance.abs() == 2 {
+                now = self.rebalance_node(&now, balance);
+                if now.borrow().get_balance_factor() != 0 {
+                    return;
+                }
+            } else {
+                now.borrow_mut().set_balance_factor(balance);
+                if balance != 0 {
+                    return;
+                }
+            }
+
+            // nowを根とする部分木の高さが1減った
+            match Self::parent_and_side(&now) {
+                Some((parent, s)) => {
+                    now = parent;
+                    side = s;
+                },
+                None => return,
+            }
+        }
+    }
+
+    /*
+        -1 <= left.height - right.height <= 1 で、保存されているバランス係数と一致するかを確認する
+        部分木の高さを返す 葉は0
+    */
+    #[cfg(any(test, feature = "debug-validate"))]
+    pub(in crate::avl) fn dfs_validate_balance(node: &NodeRef<T>) -> Result<i32, ValidationError<T>> {
+        let left_height = match &node.borrow().left {
+            Some(v) => Self::dfs_validate_balance(v)? + 1,
+            None => 0,
+        };
+        let right_height = match &node.borrow().right {
+            Some(v) => Self::dfs_validate_balance(v)? + 1,
+            None => 0,
+        };
+
+        let actual = left_height - right_height;
+        if !(-1..=1).contains(&actual) {
+            return Err( ValidationError::Balance { value: node.borrow().data.clone(), balance_factor: actual } );
+        }
+        let stored = node.borrow().get_balance_factor();
+        if stored != actual {
+            return Err( ValidationError::BalanceFactor { value: node.borrow().data.clone(), stored, actual } );
+        }
+        Ok( left_height.max(right_height) )
+    }
+}
@@ -0,0 +1,208 @@
+use alloc::string::String;
+
+use super::super::{MultiAVL, NodeRef, NodeSide, Rc};
+#[cfg(any(test, feature = "debug-validate"))]
+use super::super::ValidationError;
+use super::{BalancePolicy, sealed::Rebalance};
+
+/*
+    赤黒木 ノードの2ビットのうち1ビットを色に使う
+    AVL木より回転が少ない代わりに、高さは最大で約2倍になる
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RedBlack;
+
+const BLACK: u32 = 0;
+const RED: u32 = 1;
+
+impl BalancePolicy for RedBlack {}
+
+impl Rebalance for RedBlack {
+    fn after_insert<T: Ord + Clone>(tree: &mut MultiAVL<T, Self>, node: &NodeRef<T>) {
+        tree.fix_after_insert(node);
+    }
+
+    fn after_erase<T: Ord + Clone>(tree: &mut MultiAVL<T, Self>, removed: &NodeRef<T>, position: Option<(NodeRef<T>, NodeSide)>) {
+        tree.fix_after_erase(removed, position);
+    }
+
+    fn built_bits(_left_len: usize, _right_len: usize, depth: usize, len: usize) -> u32 {
+        // 最も深い段以外は埋まっているので、最も深い段だけを赤にすれば黒の高さが揃う
+        let max_depth = len.ilog2() as usize;
+        if depth == max_depth && depth > 0 { RED } else { BLACK }
+    }
+
+    fn node_attribute<T: Clone>(node: &NodeRef<T>) -> (&'static str, String) {
+        let color = if is_red(node) { "red" } else { "black" };
+        ("color", String::from(color))
+    }
+
+    #[cfg(any(test, feature = "debug-validate"))]
+    fn validate_balance<T: Ord + Clone>(tree: &MultiAVL<T, Self>) -> Result<(), ValidationError<T>> {
+        if let Some(v) = &tree.root {
+            if is_red(v) {
+                return Err( ValidationError::Color { value: v.borrow().data.clone() } );
+            }
+            MultiAVL::<T, Self>::dfs_validate_color(v)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_red<T: Clone>(node: &NodeRef<T>) -> bool {
+    node.borrow().balance_bits() == RED
+}
+
+// 空の子は黒として扱う
+fn is_red_child<T: Clone>(node: &Option<NodeRef<T>>) -> bool {
+    node.as_ref().is_some_and(is_red)
+}
+
+fn set_color<T: Clone>(node: &NodeRef<T>, color: u32) {
+    node.borrow_mut().set_balance_bits(color);
+}
+
+fn child<T: Clone>(node: &NodeRef<T>, side: NodeSide) -> Option<NodeRef<T>> {
+    match side {
+        NodeSide::Left => node.borrow().left.as_ref().map(Rc::clone),
+        NodeSide::Right => node.borrow().right.as_ref().map(Rc::clone),
+    }
+}
+
+impl<T: Ord + Clone> MultiAVL<T, RedBlack> {
+    // sideがLeftなら左回転、Rightなら右回転
+    fn rotate_toward(&mut self, node: &NodeRef<T>, side: NodeSide) {
+        match side {
+            NodeSide::Left => self.rotate_left(node),
+            NodeSide::Right => self.rotate_right(node),
+        }
+    }
+
+    // 新しいノードを赤にして、赤が続くところを直す
+    fn fix_after_insert(&mut self, node: &NodeRef<T>) {
+        set_color(node, RED);
+        let mut now = Rc::clone(node);
+        while let Some((parent, side)) = Self::parent_and_side(&now) {
+            if !is_red(&parent) {
+                break;
+            }
+            // 根は黒なので赤の親には親がいる
+            let (grand, parent_side) = Self::parent_and_side(&parent).unwrap();
+            let uncle = child(&grand, parent_side.opposite());
+            if let Some(uncle) = uncle.filter(is_red) {
+                // 叔父も赤なら塗り替えて、祖父から続ける
+                set_color(&parent, BLACK);
+                set_color(&uncle, BLACK);
+                set_color(&grand, RED);
+                now = grand;
+                continue;
+            }
+
+            // 内側の孫なら親を回転して外側に移す
+            let top = if side != parent_side {
+                self.rotate_toward(&parent, parent_side);
+                now
+            } else {
+                parent
+            };
+            set_color(&top, BLACK);
+            set_color(&grand, RED);
+            self.rotate_toward(&grand, parent_side.opposite());
+            break;
+        }
+
+        if let Some(root) = &self.root {
+            set_color(root, BLACK);
+        }
+    }
+
+    // 黒のノードを外したときは、外した側の黒が１つ足りなくなるので直す
+    fn fix_after_erase(&mut self, removed: &NodeRef<T>, position: Option<(NodeRef<T>, NodeSide)>) {
+        if is_red(removed) {
+            return;
+        }
+
+        // removedの場所に入った子が赤なら黒にすれば済む
+        let replacement = match &position {
+            Some((parent, side)) => child(parent, *side),
+            None => self.root.as_ref().map(Rc::clone),
+        };
+        if let Some(v) = replacement.filter(is_red) {
+            set_color(&v, BLACK);
+            return;
+        }
+
+        let (mut parent, mut side) = match position {
+            Some(v) => v,
+            None => return,
+        };
+        loop {
+            // 足りない側の反対には黒が１つ以上あるので兄弟がいる
+            let mut sibling = child(&parent, side.opposite()).unwrap();
+            if is_red(&sibling) {
+                set_color(&sibling, BLACK);
+                set_color(&parent, RED);
+                self.rotate_toward(&parent, side);
+                sibling = child(&parent, side.opposite()).unwrap();
+            }
+
+            let near = child(&sibling, side);
+            let far = child(&sibling, side.opposite());
+            if !is_red_child(&near) && !is_red_child(&far) {
+                // 兄弟を赤にして、足りない黒を親に押し上げる
+                set_color(&sibling, RED);
+                if is_red(&parent) {
+                    set_color(&parent, BLACK);
+                    return;
+                }
+                match Self::parent_and_side(&parent) {
+                    Some((p, s)) => {
+                        parent = p;
+                        side = s;
+                    },
+                    None => return,
+                }
+                continue;
+            }
+
+            if !is_red_child(&far) {
+                // 近い方の甥だけが赤なら、兄弟を回転して遠い方に移す
+                set_color(near.as_ref().unwrap(), BLACK);
+                set_color(&sibling, RED);
+                self.rotate_toward(&sibling, side.opposite());
+                sibling = child(&parent, side.opposite()).unwrap();
+            }
+
+            let far = child(&sibling, side.opposite()).unwrap();
+            let parent_color = parent.borrow().balance_bits();
+            set_color(&sibling, parent_color);
+            set_color(&parent, BLACK);
+            set_color(&far, BLACK);
+            self.rotate_toward(&parent, side);
+            return;
+        }
+    }
+
+    /*
+        赤のノードの子が黒で、どの葉までの黒の数も等しいかを確認する
+        部分木の黒の高さを返す
+    */
+    #[cfg(any(test, feature = "debug-validate"))]
+    fn dfs_validate_color(node: &NodeRef<T>) -> Result<usize, ValidationError<T>> {
+        let mut heights = [0, 0];
+        for (i, child) in [&node.borrow().left, &node.borrow().right].into_iter().enumerate() {
+            if let Some(v) = child {
+                if is_red(node) && is_red(v) {
+                    return Err( ValidationError::Color { value: v.borrow().data.clone() } );
+                }
+                heights[i] = Self::dfs_validate_color(v)?;
+            }
+        }
+
+        let [left, right] = heights;
+        if left != right {
+            return Err( ValidationError::BlackHeight { value: node.borrow().data.clone(), left, right } );
+        }
+        Ok( left + if is_red(node) { 0 } else { 1 } )
+    }
+}
@@ -0,0 +1,100 @@
+use core::borrow;
+use core::ops::Deref;
+
+use super::{Avl, BalancePolicy, MultiAVL};
+
+// 容量を超えたときにどちらの端を残すか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keep {
+    // 大きい方を残し、最小の要素を追い出す
+    Largest,
+    // 小さい方を残し、最大の要素を追い出す
+    Smallest,
+}
+
+/*
+    要素数がcapacityを超えない多重集合 上位k件を保つのに使う
+    満杯のときに残らない値は、キャッシュしている最小・最大のノードとの比較だけで O(1) で弾く
+*/
+pub struct BoundedMultiAVL<T, P = Avl>
+    where T: Ord + Clone, P: BalancePolicy
+{
+    tree: MultiAVL<T, P>,
+    capacity: usize,
+    keep: Keep,
+}
+
+// 読み取りはMultiAVLのメソッドをそのまま使う
+impl<T: Ord + Clone, P: BalancePolicy> Deref for BoundedMultiAVL<T, P> {
+    type Target = MultiAVL<T, P>;
+
+    fn deref(&self) -> &MultiAVL<T, P> {
+        &self.tree
+    }
+}
+
+impl<T: Ord + Clone> BoundedMultiAVL<T> {
+    pub fn new(capacity: usize, keep: Keep) -> BoundedMultiAVL<T> {
+        Self::with_policy(capacity, keep)
+    }
+}
+
+impl<T: Ord + Clone, P: BalancePolicy> BoundedMultiAVL<T, P> {
+    pub fn with_policy(capacity: usize, keep: Keep) -> BoundedMultiAVL<T, P> {
+        BoundedMultiAVL { tree: MultiAVL::with_policy(), capacity, keep }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn keep(&self) -> Keep {
+        self.keep
+    }
+
+    /*
+        容量を超えたら追い出した値を返す 入れた値自身が残らないときはそれを返す
+        残す端と等しい値は、先に入っていた方を残す
+    */
+    pub fn insert(&mut self, value: T) -> Option<T> {
+        if self.tree.size() < self.capacity {
+            self.tree.insert(value);
+            return None;
+        }
+        if self.rejects(&value) {
+            return Some( value );
+        }
+
+        self.tree.insert(value);
+        // 端のノードはキャッシュしているので探索せずに消せる
+        let iter = match self.keep {
+            Keep::Largest => self.tree.min_iter(),
+            Keep::Smallest => self.tree.max_iter(),
+        };
+        self.tree.erase_iter(iter.unwrap()).ok()
+    }
+
+    pub fn erase<Q>(&mut self, value: &Q) -> bool
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        self.tree.erase(value)
+    }
+
+    pub fn into_inner(self) -> MultiAVL<T, P> {
+        self.tree
+    }
+
+    // 満杯のときにvalueを入れても残らないか
+    fn rejects(&self, value: &T) -> bool {
+        let extreme = match self.keep {
+            Keep::Largest => &self.tree.min_node,
+            Keep::Smallest => &self.tree.max_node,
+        };
+        match (extreme, self.keep) {
+            (Some(node), Keep::Largest) => *value <= node.borrow().data,
+            (Some(node), Keep::Smallest) => *value >= node.borrow().data,
+            // 容量0
+            (None, _) => true,
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use alloc::vec::Vec;
+
+use super::{BalancePolicy, MultiAVL, Node, NodeRef, OverflowCounts, Rc, RefCell};
+
+impl<T: Ord + Clone, P: BalancePolicy> MultiAVL<T, P> {
+    // 昇順に並んだ値から線形時間で木を作る 昇順でなければpanicする
+    pub fn from_sorted_iter<I: IntoIterator<Item = T>>(iter: I) -> MultiAVL<T, P> {
+        let counts = Self::sorted_counts(iter);
+        let mut tree = Self::with_policy();
+        let root = (!counts.is_empty()).then(|| Self::build_subtree(&counts, 0, counts.len(), &tree.overflow));
+        tree.set_built_root(root, &counts);
+        tree
+    }
+
+    // 同じ値をまとめて(値, 個数)の列にする
+    pub(super) fn sorted_counts<I: IntoIterator<Item = T>>(iter: I) -> Vec<(T, usize)> {
+        let mut counts: Vec<(T, usize)> = Vec::new();
+        for value in iter {
+            match counts.last_mut() {
+                Some((last, count)) if *last == value => *count += 1,
+                Some((last, _)) if *last > value => panic!("from_sorted_iter: values are not sorted"),
+                _ => counts.push( (value, 1) ),
+            }
+        }
+        counts
+    }
+
+    /*
+        countsの中央を根として部分木を作る
+        depthは部分木の根の深さ、lenは木全体の(値, 個数)の数
+    */
+    pub(super) fn build_subtree(counts: &[(T, usize)], depth: usize, len: usize, overflow: &OverflowCounts) -> NodeRef<T> {
+        let mid = counts.len() / 2;
+        let node = Self::built_node(&counts[mid], overflow);
+        let left = (mid > 0).then(|| Self::build_subtree(&counts[..mid], depth + 1, len, overflow));
+        let right = (mid + 1 < counts.len()).then(|| Self::build_subtree(&counts[mid + 1..], depth + 1, len, overflow));
+        Self::link_built(&node, left, right, (mid, counts.len() - mid - 1), depth, len);
+        #[cfg(feature = "rank")]
+        super::rank::update_subtree_counts(&node, overflow);
+        node
+    }
+
+    pub(super) fn built_node((value, count): &(T, usize), overflow: &OverflowCounts) -> NodeRef<T> {
+        let node = Rc::new(RefCell::new( Node::new( value.clone(), None )));
+        overflow.set(&node, *count);
+        node
+    }
+
+    // 作った左右の部分木をnodeに繋ぐ lensは左右の部分木の(値, 個数)の数
+    pub(super) fn link_built(node: &NodeRef<T>, left: Option<NodeRef<T>>, right: Option<NodeRef<T>>, lens: (usize, usize), depth: usize, len: usize) {
+        for child in [&left, &right].into_iter().flatten() {
+            child.borrow_mut().parent = Some( Rc::downgrade(node) );
+        }
+        let mut n = node.borrow_mut();
+        n.set_balance_bits(P::built_bits(lens.0, lens.1, depth, len));
+        n.left = left;
+        n.right = right;
+    }
+
+    // 作った木を空の木に入れる
+    pub(super) fn set_built_root(&mut self, root: Option<NodeRef<T>>, counts: &[(T, usize)]) {
+        debug_assert!(self.root.is_none());
+        self.root = root;
+        self.size = counts.iter().map(|(_, count)| count).sum();
+        self.distinct = counts.len();
+        self.min_node = self.find_min_node();
+        self.max_node = self.find_max_node();
+    }
+}
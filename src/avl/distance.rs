@@ -0,0 +1,23 @@
+// nearestで使う２つの値の距離
+pub trait Distance {
+    type Output: Ord;
+    fn distance(&self, other: &Self) -> Self::Output;
+}
+
+macro_rules! impl_distance {
+    ($($t:ty => $o:ty),*) => {
+        $(
+            impl Distance for $t {
+                type Output = $o;
+                fn distance(&self, other: &Self) -> $o {
+                    self.abs_diff(*other)
+                }
+            }
+        )*
+    };
+}
+
+impl_distance!(
+    i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128, isize => usize,
+    u8 => u8, u16 => u16, u32 => u32, u64 => u64, u128 => u128, usize => usize
+);
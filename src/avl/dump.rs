@@ -0,0 +1,91 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Debug, Write};
+
+use super::{BalancePolicy, MultiAVL, NodeRef};
+
+impl<T: Ord + Clone + Debug, P: BalancePolicy> MultiAVL<T, P> {
+    // Graphvizのdot形式で木の形を出力する
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        out.push_str("digraph MultiAVL {\n");
+        out.push_str("    node [shape=box];\n");
+        if let Some(v) = &self.root {
+            let mut id = 0;
+            self.dot_node(v, &mut id, &mut out);
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    // nodeとその子孫を出力し、nodeに振ったidを返す
+    fn dot_node(&self, node: &NodeRef<T>, id: &mut usize, out: &mut String) -> usize {
+        let n = node.borrow();
+        let my_id = *id;
+        *id += 1;
+
+        let data = format!("{:?}", n.data).replace('\\', "\\\\").replace('"', "\\\"");
+        let (name, attribute) = P::node_attribute(node);
+        writeln!(out, "    n{} [label=\"{}\\ncount: {}\\nheight: {}\\n{}: {}\"];",
+            my_id, data, self.overflow.get(node), n.height(), name, attribute).unwrap();
+
+        for (child, side) in [(&n.left, "L"), (&n.right, "R")] {
+            if let Some(v) = child {
+                let child_id = self.dot_node(v, id, out);
+                writeln!(out, "    n{} -> n{} [label=\"{}\"];", my_id, child_id, side).unwrap();
+            }
+        }
+
+        my_id
+    }
+
+    /*
+        罫線で木の形を出力する
+        5×1 (height 1, balance 0)
+        ├── L: 3×2 (height 0, balance 0)
+        └── R: 8×1 (height 0, balance 0)
+    */
+    pub fn to_ascii(&self) -> String {
+        let mut out = String::new();
+        match &self.root {
+            Some(v) => self.ascii_node(v, "", "", &mut out),
+            None => out.push_str("(empty)\n"),
+        }
+        out
+    }
+
+    fn ascii_node(&self, node: &NodeRef<T>, label: &str, prefix: &str, out: &mut String) {
+        let n = node.borrow();
+        let (name, attribute) = P::node_attribute(node);
+        writeln!(out, "{}{:?}×{} (height {}, {} {})",
+            label, n.data, self.overflow.get(node), n.height(), name, attribute).unwrap();
+
+        let children: Vec<_> = [(&n.left, "L"), (&n.right, "R")]
+            .into_iter()
+            .filter_map(|(child, side)| child.as_ref().map(|v| (v, side)))
+            .collect();
+        for (i, (child, side)) in children.iter().enumerate() {
+            let is_last = i + 1 == children.len();
+            let branch = if is_last { "└── " } else { "├── " };
+            let next_prefix = if is_last { "    " } else { "│   " };
+            self.ascii_node(child, &format!("{}{}{}: ", prefix, branch, side), &format!("{}{}", prefix, next_prefix), out);
+        }
+    }
+}
+
+// {1×3, 2×1} のように値と個数を出力する
+impl<T: Ord + Clone + Debug, P: BalancePolicy> fmt::Display for MultiAVL<T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("{")?;
+        let mut first = true;
+        self.try_for_each_count(|value, count| {
+            if !first {
+                f.write_str(", ")?;
+            }
+            first = false;
+            write!(f, "{:?}×{}", value, count)
+        })?;
+        f.write_str("}")
+    }
+}
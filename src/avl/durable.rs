@@ -0,0 +1,321 @@
+use alloc::vec::Vec;
+use core::borrow;
+use core::ops::Deref;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::snapshot::Crc32;
+use super::{Avl, BalancePolicy, MultiAVL, SnapshotCodec, SnapshotError};
+
+/*
+    ディレクトリにスナップショットと先行書き込みログ(WAL)を置いて木を永続化する
+    snapshot.N: 世代Nの開始時点の木 (世代0はスナップショットを持たず空の木から始まる)
+    wal.N: 世代Nの開始後の操作 先頭にヘッダ、続けて 操作(1バイト) | 値 | CRC-32(4バイト) のレコード
+    コンパクションでは snapshot.N+1 を書き終えてから古い世代を消すので、どこで落ちても
+    一番新しいスナップショットとその世代のWALから復元できる
+*/
+const WAL_MAGIC: [u8; 4] = *b"MAVW";
+const WAL_VERSION: u8 = 1;
+const WAL_HEADER_LEN: usize = 6;
+
+const OP_INSERT: u8 = 1;
+const OP_ERASE: u8 = 2;
+
+// この数だけログが溜まったらスナップショットにまとめる
+const DEFAULT_COMPACT_THRESHOLD: usize = 4096;
+
+pub struct DurableMultiAVL<T, P = Avl>
+    where T: Ord + Clone + SnapshotCodec, P: BalancePolicy
+{
+    tree: MultiAVL<T, P>,
+    dir: PathBuf,
+    generation: u64,
+    wal: File,
+    // WALのうち正しいレコードで終わっている部分の長さ
+    wal_len: u64,
+    // 書き込みに失敗してwal_lenより後ろに書きかけのレコードが残っているかもしれない
+    torn: bool,
+    records: usize,
+    compact_threshold: usize,
+    // コンパクションが途中で失敗した 次の世代のスナップショットが残っているかもしれないので、
+    // やり直すまで今の世代のWALには書かない
+    compact_pending: bool,
+    // 次のレコードをこのバイト数だけ書いたところで失敗させる
+    #[cfg(test)]
+    fail_write: Option<usize>,
+}
+
+// 読み取りはMultiAVLのメソッドをそのまま使う
+impl<T: Ord + Clone + SnapshotCodec, P: BalancePolicy> Deref for DurableMultiAVL<T, P> {
+    type Target = MultiAVL<T, P>;
+
+    fn deref(&self) -> &MultiAVL<T, P> {
+        &self.tree
+    }
+}
+
+impl<T: Ord + Clone + SnapshotCodec, P: BalancePolicy> DurableMultiAVL<T, P> {
+    /*
+        dirの中身から木を復元する dirがなければ作る
+        WALの末尾が途中で切れていたり壊れていたら、そのレコードから後ろを捨てる
+    */
+    pub fn open<D: AsRef<Path>>(dir: D) -> Result<DurableMultiAVL<T, P>, SnapshotError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let generation = latest_generation(&dir)?;
+        let mut tree = if generation == 0 {
+            MultiAVL::with_policy()
+        } else {
+            MultiAVL::read_from(BufReader::new(File::open(snapshot_path(&dir, generation))?))?
+        };
+
+        let mut wal = OpenOptions::new().read(true).write(true).create(true).truncate(false)
+            .open(wal_path(&dir, generation))?;
+        let mut bytes = Vec::new();
+        wal.read_to_end(&mut bytes)?;
+        let (valid_len, records) = if bytes.len() < WAL_HEADER_LEN {
+            // ヘッダを書く前に落ちた read_to_endで進んだ位置を先頭に戻してから書く
+            wal.set_len(0)?;
+            wal.seek(SeekFrom::Start(0))?;
+            wal.write_all(&wal_header::<T>())?;
+            (WAL_HEADER_LEN, 0)
+        } else {
+            check_wal_header::<T>(&bytes)?;
+            replay(&mut tree, &bytes)
+        };
+        wal.set_len(valid_len as u64)?;
+        wal.seek(SeekFrom::End(0))?;
+        wal.sync_all()?;
+
+        remove_stale_files(&dir, generation)?;
+        Ok(DurableMultiAVL {
+            tree, dir, generation, wal,
+            wal_len: valid_len as u64,
+            torn: false,
+            records,
+            compact_threshold: DEFAULT_COMPACT_THRESHOLD,
+            compact_pending: false,
+            #[cfg(test)]
+            fail_write: None,
+        })
+    }
+
+    /*
+        ログがthreshold件溜まったら、次の書き込みの前にコンパクションを行う
+        書き込みの後にまとめると、ログに書けた操作をコンパクションの失敗でエラーとして返すことになる
+        0ならコンパクションはcompact()を呼んだときだけ行う
+    */
+    pub fn set_compact_threshold(&mut self, threshold: usize) {
+        self.compact_threshold = threshold;
+    }
+
+    // 挿入後のvalueの個数を返す
+    pub fn insert(&mut self, value: T) -> io::Result<usize> {
+        self.compact_if_needed()?;
+        self.append(OP_INSERT, &value)?;
+        Ok( self.tree.insert(value) )
+    }
+
+    // 値がなければログにも書かずにfalseを返す
+    pub fn erase<Q>(&mut self, value: &Q) -> io::Result<bool>
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        // ログには木の中の値を書く
+        let stored = match self.tree.find_node(value) {
+            Some(node) => node.borrow().data.clone(),
+            None => return Ok(false),
+        };
+        self.compact_if_needed()?;
+        self.append(OP_ERASE, &stored)?;
+        self.tree.erase(value);
+        Ok(true)
+    }
+
+    /*
+        ログはOSに渡した時点でプロセスが落ちても残るが、電源断に備えるならsyncを呼ぶ
+    */
+    pub fn sync(&self) -> io::Result<()> {
+        self.wal.sync_data()
+    }
+
+    // 今の木をスナップショットに書き、ログを空にした新しい世代に移る
+    pub fn compact(&mut self) -> io::Result<()> {
+        self.compact_pending = true;
+        let next = self.generation + 1;
+        let tmp = self.dir.join(format!("snapshot.{}.tmp", next));
+        let file = File::create(&tmp)?;
+        self.tree.write_to(BufWriter::new(&file))?;
+        file.sync_all()?;
+        fs::rename(&tmp, snapshot_path(&self.dir, next))?;
+        sync_dir(&self.dir)?;
+
+        let mut wal = File::create(wal_path(&self.dir, next))?;
+        wal.write_all(&wal_header::<T>())?;
+        wal.sync_all()?;
+
+        self.wal = wal;
+        self.wal_len = WAL_HEADER_LEN as u64;
+        self.torn = false;
+        self.generation = next;
+        self.records = 0;
+        self.compact_pending = false;
+        remove_stale_files(&self.dir, next)
+    }
+
+    pub fn into_inner(self) -> MultiAVL<T, P> {
+        self.tree
+    }
+
+    /*
+        レコードは１回のwrite_allで書き、途中で落ちても末尾が切れるだけにする
+        書き込みがエラーになったら書きかけの部分を切り捨てる 切り捨てにも失敗したら、
+        次のレコードが読めない部分の後ろに書かれないよう、次の書き込みの前にもう一度切り捨てる
+    */
+    fn append(&mut self, op: u8, value: &T) -> io::Result<()> {
+        let mut record = Vec::from([op]);
+        value.encode(&mut record)?;
+        let mut crc = Crc32::new();
+        crc.update(&record);
+        record.extend_from_slice(&crc.finish().to_le_bytes());
+
+        if self.torn {
+            self.truncate_torn()?;
+        }
+        if let Err(e) = self.write_record(&record) {
+            self.torn = true;
+            let _ = self.truncate_torn();
+            return Err(e);
+        }
+        self.wal_len += record.len() as u64;
+        self.records += 1;
+        Ok(())
+    }
+
+    fn write_record(&mut self, record: &[u8]) -> io::Result<()> {
+        #[cfg(test)]
+        if let Some(len) = self.fail_write.take() {
+            self.wal.write_all(&record[..len])?;
+            return Err( io::Error::other("injected write failure") );
+        }
+        self.wal.write_all(record)
+    }
+
+    fn truncate_torn(&mut self) -> io::Result<()> {
+        self.wal.set_len(self.wal_len)?;
+        self.wal.seek(SeekFrom::Start(self.wal_len))?;
+        self.torn = false;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn fail_next_write(&mut self, written: usize) {
+        self.fail_write = Some(written);
+    }
+
+    fn compact_if_needed(&mut self) -> io::Result<()> {
+        if self.compact_pending || (self.compact_threshold > 0 && self.records >= self.compact_threshold) {
+            self.compact()?;
+        }
+        Ok(())
+    }
+}
+
+fn snapshot_path(dir: &Path, generation: u64) -> PathBuf {
+    dir.join(format!("snapshot.{}", generation))
+}
+
+fn wal_path(dir: &Path, generation: u64) -> PathBuf {
+    dir.join(format!("wal.{}", generation))
+}
+
+// ファイル名から(種類, 世代)を読む
+fn parse_file_name(name: &str) -> Option<(&str, u64)> {
+    let (kind, generation) = name.split_once('.')?;
+    Some( (kind, generation.parse().ok()?) )
+}
+
+fn latest_generation(dir: &Path) -> io::Result<u64> {
+    let mut latest = 0;
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        if let Some(("snapshot", generation)) = name.to_str().and_then(parse_file_name) {
+            latest = latest.max(generation);
+        }
+    }
+    Ok(latest)
+}
+
+// 今の世代以外のファイルと、書きかけのスナップショットを消す
+fn remove_stale_files(dir: &Path, generation: u64) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let stale = match name.to_str() {
+            Some(name) if name.ends_with(".tmp") => name.starts_with("snapshot."),
+            Some(name) => matches!(parse_file_name(name), Some(("snapshot" | "wal", g)) if g != generation),
+            None => false,
+        };
+        if stale {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+// リネームをディレクトリに反映させる
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+fn wal_header<T: SnapshotCodec>() -> [u8; WAL_HEADER_LEN] {
+    let [a, b, c, d] = WAL_MAGIC;
+    [a, b, c, d, WAL_VERSION, T::TYPE_TAG]
+}
+
+fn check_wal_header<T: SnapshotCodec>(bytes: &[u8]) -> Result<(), SnapshotError> {
+    if bytes[..4] != WAL_MAGIC {
+        return Err( SnapshotError::BadMagic );
+    }
+    if bytes[4] != WAL_VERSION {
+        return Err( SnapshotError::UnsupportedVersion(bytes[4]) );
+    }
+    if bytes[5] != T::TYPE_TAG {
+        return Err( SnapshotError::TypeMismatch { expected: T::TYPE_TAG, found: bytes[5] } );
+    }
+    Ok(())
+}
+
+// 正しいレコードを順に木に適用し、(正しい部分の長さ, レコード数)を返す
+fn replay<T: Ord + Clone + SnapshotCodec, P: BalancePolicy>(tree: &mut MultiAVL<T, P>, bytes: &[u8]) -> (usize, usize) {
+    let mut pos = WAL_HEADER_LEN;
+    let mut records = 0;
+    while let Some((op, value, len)) = read_record::<T>(&bytes[pos..]) {
+        match op {
+            OP_INSERT => { tree.insert(value); },
+            _ => { tree.erase(&value); },
+        }
+        pos += len;
+        records += 1;
+    }
+    (pos, records)
+}
+
+fn read_record<T: SnapshotCodec>(bytes: &[u8]) -> Option<(u8, T, usize)> {
+    let (&op, mut rest) = bytes.split_first()?;
+    if op != OP_INSERT && op != OP_ERASE {
+        return None;
+    }
+    let value = T::decode(&mut rest).ok()?;
+    let body_len = bytes.len() - rest.len();
+    let stored = u32::from_le_bytes(rest.get(..4)?.try_into().unwrap());
+
+    let mut crc = Crc32::new();
+    crc.update(&bytes[..body_len]);
+    (crc.finish() == stored).then_some( (op, value, body_len + 4) )
+}
@@ -0,0 +1,100 @@
+use super::{Avl, BalancePolicy, MultiAVL, NodeRef, InsertPosition, Operation};
+
+pub enum Entry<'a, T, P = Avl>
+    where T: Ord + Clone, P: BalancePolicy
+{
+    Occupied(OccupiedEntry<'a, T, P>),
+    Vacant(VacantEntry<'a, T, P>),
+}
+
+pub struct OccupiedEntry<'a, T, P = Avl>
+    where T: Ord + Clone, P: BalancePolicy
+{
+    pub(super) tree: &'a mut MultiAVL<T, P>,
+    pub(super) node: NodeRef<T>,
+}
+
+pub struct VacantEntry<'a, T, P = Avl>
+    where T: Ord + Clone, P: BalancePolicy
+{
+    pub(super) tree: &'a mut MultiAVL<T, P>,
+    pub(super) value: T,
+    pub(super) position: InsertPosition<T>,
+}
+
+impl<'a, T: Ord + Clone, P: BalancePolicy> Entry<'a, T, P> {
+    pub fn count(&self) -> usize {
+        match self {
+            Entry::Occupied(v) => v.count(),
+            Entry::Vacant(_) => 0,
+        }
+    }
+
+    pub fn insert_n(self, n: usize) {
+        match self {
+            Entry::Occupied(mut v) => v.insert_n(n),
+            Entry::Vacant(v) => v.insert_n(n),
+        }
+    }
+}
+
+// 既に存在する値の個数の操作 個数が0にならない限り木の形は変わらない
+impl<'a, T: Ord + Clone, P: BalancePolicy> OccupiedEntry<'a, T, P> {
+    pub fn get(&self) -> T {
+        self.node.borrow().data.clone()
+    }
+
+    pub fn count(&self) -> usize {
+        self.tree.overflow.get(&self.node)
+    }
+
+    pub fn insert_n(&mut self, n: usize) {
+        if n == 0 { return; }
+        assert!(self.tree.can_insert(n), "insert: element count overflow");
+        self.tree.record(|| Operation::Insert(self.node.borrow().data.clone(), n));
+        self.tree.increase_count(&self.node, n);
+    }
+
+    // 最大n個を削除し、実際に削除した個数を返す
+    pub fn remove_n(self, n: usize) -> usize {
+        let removed = n.min(self.count());
+        if removed == 0 { return 0; }
+        self.tree.record(|| Operation::Erase(self.node.borrow().data.clone(), removed));
+        self.tree.decrease_count(&self.node, removed);
+        removed
+    }
+
+    // 個数をnにする 0ならノードごと削除する
+    pub fn set_count(mut self, n: usize) {
+        let count = self.count();
+        if n > count {
+            self.insert_n(n - count);
+        } else {
+            self.remove_n(count - n);
+        }
+    }
+
+    // ノードごと削除し、値と削除した個数を返す
+    pub fn remove_entry(self) -> (T, usize) {
+        let value = self.get();
+        let count = self.remove_n(usize::MAX);
+        (value, count)
+    }
+}
+
+impl<'a, T: Ord + Clone, P: BalancePolicy> VacantEntry<'a, T, P> {
+    pub fn key(&self) -> &T {
+        &self.value
+    }
+
+    pub fn insert(self) {
+        self.insert_n(1);
+    }
+
+    pub fn insert_n(self, n: usize) {
+        if n == 0 { return; }
+        assert!(self.tree.can_insert(n), "insert: element count overflow");
+        self.tree.record(|| Operation::Insert(self.value.clone(), n));
+        self.tree.insert_at(self.position, self.value, n);
+    }
+}
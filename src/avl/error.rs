@@ -0,0 +1,35 @@
+use core::fmt;
+
+// erase_iterで消せなかった理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IterError {
+    // 指していた要素が既に消されている
+    Dangling,
+    // 最後の要素より後ろを指している
+    Exhausted,
+}
+
+impl fmt::Display for IterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IterError::Dangling => write!(f, "iterator points to an erased element"),
+            IterError::Exhausted => write!(f, "iterator is exhausted"),
+        }
+    }
+}
+
+impl core::error::Error for IterError {}
+
+// try_insertで要素数がusizeに収まらなくなる 入れようとした値を返す
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountOverflow<T> {
+    pub value: T,
+}
+
+impl<T: fmt::Debug> fmt::Display for CountOverflow<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "inserting {:?} overflows the element count", self.value)
+    }
+}
+
+impl<T: fmt::Debug> core::error::Error for CountOverflow<T> {}
@@ -0,0 +1,190 @@
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::ops::Range;
+
+use super::{Avl, BalancePolicy, MultiAVL, NodeRef};
+
+/*
+    半開区間[start, end)の多重集合 startの順に並べ、各ノードに部分木のendの最大値を持たせる
+    endの最大値がa以下の部分木には[a, b)と重なる区間がないので探索を打ち切れる
+*/
+#[derive(Debug, Clone)]
+struct Interval<K> {
+    start: K,
+    end: K,
+    // このノードを根とする部分木のendの最大値
+    max_end: K,
+}
+
+impl<K: Clone> Interval<K> {
+    fn new(range: &Range<K>) -> Self {
+        Interval { start: range.start.clone(), end: range.end.clone(), max_end: range.end.clone() }
+    }
+
+    fn range(&self) -> Range<K> {
+        self.start.clone()..self.end.clone()
+    }
+}
+
+// max_endは順序に含めない
+impl<K: Ord> Ord for Interval<K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&self.start, &self.end).cmp(&(&other.start, &other.end))
+    }
+}
+
+impl<K: Ord> PartialOrd for Interval<K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some( self.cmp(other) )
+    }
+}
+
+impl<K: Ord> PartialEq for Interval<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K: Ord> Eq for Interval<K> {}
+
+// 個数はendの最大値に関係しない
+fn update_max_end<K: Ord + Clone>(node: &NodeRef<Interval<K>>, _count: usize) {
+    let mut max_end = node.borrow().data.end.clone();
+    for child in [&node.borrow().left, &node.borrow().right].into_iter().flatten() {
+        let child = child.borrow();
+        if child.data.max_end > max_end {
+            max_end = child.data.max_end.clone();
+        }
+    }
+    node.borrow_mut().data.max_end = max_end;
+}
+
+pub struct IntervalMultiAVL<K, P = Avl>
+    where K: Ord + Clone, P: BalancePolicy
+{
+    tree: MultiAVL<Interval<K>, P>,
+}
+
+impl<K: Ord + Clone, P: BalancePolicy> Default for IntervalMultiAVL<K, P> {
+    fn default() -> Self {
+        Self::with_policy()
+    }
+}
+
+impl<K: Ord + Clone> IntervalMultiAVL<K> {
+    pub fn new() -> IntervalMultiAVL<K> {
+        Self::with_policy()
+    }
+}
+
+impl<K: Ord + Clone, P: BalancePolicy> IntervalMultiAVL<K, P> {
+    pub fn with_policy() -> IntervalMultiAVL<K, P> {
+        let mut tree = MultiAVL::with_policy();
+        tree.set_augment(update_max_end::<K>);
+        IntervalMultiAVL { tree }
+    }
+
+    // 区間の数(重複を含む)
+    pub fn size(&self) -> usize {
+        self.tree.size()
+    }
+
+    pub fn distinct_len(&self) -> usize {
+        self.tree.distinct_len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    // 空の区間はどの区間とも重ならないので入れられない 挿入後の個数を返す
+    pub fn insert(&mut self, range: Range<K>) -> usize {
+        assert!(range.start < range.end, "IntervalMultiAVL::insert: interval is empty");
+        self.tree.insert(Interval::new(&range))
+    }
+
+    pub fn erase(&mut self, range: &Range<K>) -> bool {
+        self.tree.erase(&Interval::new(range))
+    }
+
+    pub fn count(&self, range: &Range<K>) -> usize {
+        self.tree.count(&Interval::new(range))
+    }
+
+    pub fn contains(&self, range: &Range<K>) -> bool {
+        self.tree.contains(&Interval::new(range))
+    }
+
+    // (区間, 個数)をstart, endの順に返す
+    pub fn iter_counts(&self) -> impl Iterator<Item = (Range<K>, usize)> + '_ {
+        self.tree.iter_counts().map(|(v, count)| (v.range(), count))
+    }
+
+    // [range.start, range.end)と重なる(区間, 個数)をstartの順に返す 空の区間はどれとも重ならない
+    pub fn overlapping(&self, range: Range<K>) -> Vec<(Range<K>, usize)> {
+        let mut out = Vec::new();
+        if range.is_empty() {
+            return out;
+        }
+        if let Some(root) = &self.tree.root {
+            self.dfs_overlapping(root, &range.start, &|start| *start < range.end, &mut out);
+        }
+        out
+    }
+
+    // pointを含む(区間, 個数)をstartの順に返す
+    pub fn stabbing(&self, point: &K) -> Vec<(Range<K>, usize)> {
+        let mut out = Vec::new();
+        if let Some(root) = &self.tree.root {
+            self.dfs_overlapping(root, point, &|start| start <= point, &mut out);
+        }
+        out
+    }
+
+    /*
+        endがafterより大きく、startがstarts_beforeを満たす区間を集める
+        startの順に並んでいるので、startが条件を満たさなければ右の部分木も満たさない
+    */
+    fn dfs_overlapping(&self, node: &NodeRef<Interval<K>>, after: &K, starts_before: &dyn Fn(&K) -> bool, out: &mut Vec<(Range<K>, usize)>) {
+        let n = node.borrow();
+        if n.data.max_end <= *after {
+            return;
+        }
+        if let Some(left) = &n.left {
+            self.dfs_overlapping(left, after, starts_before, out);
+        }
+        if !starts_before(&n.data.start) {
+            return;
+        }
+        if n.data.end > *after {
+            out.push( (n.data.range(), self.tree.overflow.get(node)) );
+        }
+        if let Some(right) = &n.right {
+            self.dfs_overlapping(right, after, starts_before, out);
+        }
+    }
+}
+
+// テスト用関数 元の木の不変条件とmax_endを確認する
+#[cfg(test)]
+impl<K: Ord + Clone + core::fmt::Debug + 'static, P: BalancePolicy> IntervalMultiAVL<K, P> {
+    pub fn check_consistent(&self) -> Result<(), alloc::boxed::Box<dyn std::error::Error>> {
+        self.tree.check_consistent()?;
+        if let Some(root) = &self.tree.root {
+            Self::dfs_check_max_end(root)?;
+        }
+        Ok(())
+    }
+
+    fn dfs_check_max_end(node: &NodeRef<Interval<K>>) -> Result<K, alloc::boxed::Box<dyn std::error::Error>> {
+        let n = node.borrow();
+        let mut max_end = n.data.end.clone();
+        for child in [&n.left, &n.right].into_iter().flatten() {
+            max_end = max_end.max(Self::dfs_check_max_end(child)?);
+        }
+        if n.data.max_end != max_end {
+            return Err( alloc::format!("max end of {:?} is {:?} but should be {:?}", n.data.range(), n.data.max_end, max_end).into() );
+        }
+        Ok(max_end)
+    }
+}
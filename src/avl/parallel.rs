@@ -0,0 +1,219 @@
+use alloc::vec::Vec;
+use core::iter;
+
+use rayon::iter::ParallelIterator;
+use rayon::iter::plumbing::{bridge_unindexed, Folder, UnindexedConsumer, UnindexedProducer};
+
+use super::{BalancePolicy, MultiAVL, NodeRef, OverflowCounts, Rc};
+
+// これより小さい範囲は１つのスレッドで処理する
+const SEQUENTIAL_THRESHOLD: usize = 1 << 10;
+
+impl<T: Ord + Clone + Send + Sync, P: BalancePolicy> MultiAVL<T, P> {
+    // 重複を含めた全要素を部分木ごとに分けて並列に処理する collectすれば昇順になる
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = T> + '_ {
+        self.par_iter_counts().flat_map_iter(|(value, count)| iter::repeat_n(value, count))
+    }
+
+    pub fn par_iter_counts(&self) -> MultiAVLTreeParCountIter<'_, T> {
+        MultiAVLTreeParCountIter { root: self.root.as_ref().map(Rc::clone), overflow: &self.overflow }
+    }
+
+    // from_sorted_iterと同じ木を、左右の部分木を並列に作って作る
+    pub fn par_from_sorted_iter<I: IntoIterator<Item = T>>(iter: I) -> MultiAVL<T, P> {
+        Self::par_from_sorted_counts(&Self::sorted_counts(iter))
+    }
+
+    // 両方に含まれる値を多い方の個数で持つ木を作る
+    pub fn par_union(&self, other: &Self) -> MultiAVL<T, P> {
+        let (a, b) = rayon::join(|| self.par_iter_counts().collect::<Vec<_>>(), || other.par_iter_counts().collect::<Vec<_>>());
+        Self::par_from_sorted_counts(&par_merge(&a, &b, true, usize::max))
+    }
+
+    // 両方に含まれる値だけを少ない方の個数で持つ木を作る
+    pub fn par_intersection(&self, other: &Self) -> MultiAVL<T, P> {
+        let (a, b) = rayon::join(|| self.par_iter_counts().collect::<Vec<_>>(), || other.par_iter_counts().collect::<Vec<_>>());
+        Self::par_from_sorted_counts(&par_merge(&a, &b, false, usize::min))
+    }
+
+    fn par_from_sorted_counts(counts: &[(T, usize)]) -> MultiAVL<T, P> {
+        let mut tree = Self::with_policy();
+        let root = (!counts.is_empty()).then(|| Self::par_build_subtree(counts, 0, counts.len(), &tree.overflow));
+        tree.set_built_root(root, counts);
+        tree
+    }
+
+    fn par_build_subtree(counts: &[(T, usize)], depth: usize, len: usize, overflow: &OverflowCounts) -> NodeRef<T> {
+        if counts.len() <= SEQUENTIAL_THRESHOLD {
+            return Self::build_subtree(counts, depth, len, overflow);
+        }
+
+        let mid = counts.len() / 2;
+        let node = Self::built_node(&counts[mid], overflow);
+        let (left, right) = rayon::join(
+            || Self::par_build_subtree(&counts[..mid], depth + 1, len, overflow),
+            || Self::par_build_subtree(&counts[mid + 1..], depth + 1, len, overflow),
+        );
+        Self::link_built(&node, Some(left), Some(right), (mid, counts.len() - mid - 1), depth, len);
+        #[cfg(feature = "rank")]
+        super::rank::update_subtree_counts(&node, overflow);
+        node
+    }
+}
+
+/*
+    昇順の(値, 個数)の列を２つ合わせる
+    大きい方の列の中央の値でもう片方を二分探索して分け、前後を並列に合わせる
+    keep_unmatchedなら片方にしかない値も残し、両方にある値の個数はcombineで決める
+*/
+fn par_merge<T>(a: &[(T, usize)], b: &[(T, usize)], keep_unmatched: bool, combine: fn(usize, usize) -> usize) -> Vec<(T, usize)>
+    where T: Ord + Clone + Send + Sync
+{
+    if a.len() < b.len() {
+        return par_merge(b, a, keep_unmatched, combine);
+    }
+    if a.len() + b.len() <= SEQUENTIAL_THRESHOLD {
+        return merge(a, b, keep_unmatched, combine);
+    }
+
+    // aの中央の値と等しいbの値は、どちらも後半に入る
+    let mid = a.len() / 2;
+    let split = b.partition_point(|(v, _)| *v < a[mid].0);
+    let (mut front, back) = rayon::join(
+        || par_merge(&a[..mid], &b[..split], keep_unmatched, combine),
+        || par_merge(&a[mid..], &b[split..], keep_unmatched, combine),
+    );
+    front.extend(back);
+    front
+}
+
+fn merge<T: Ord + Clone>(a: &[(T, usize)], b: &[(T, usize)], keep_unmatched: bool, combine: fn(usize, usize) -> usize) -> Vec<(T, usize)> {
+    let mut out = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].0.cmp(&b[j].0) {
+            core::cmp::Ordering::Less => {
+                if keep_unmatched {
+                    out.push( a[i].clone() );
+                }
+                i += 1;
+            },
+            core::cmp::Ordering::Greater => {
+                if keep_unmatched {
+                    out.push( b[j].clone() );
+                }
+                j += 1;
+            },
+            core::cmp::Ordering::Equal => {
+                out.push( (a[i].0.clone(), combine(a[i].1, b[j].1)) );
+                i += 1;
+                j += 1;
+            },
+        }
+    }
+    if keep_unmatched {
+        out.extend_from_slice(&a[i..]);
+        out.extend_from_slice(&b[j..]);
+    }
+    out
+}
+
+// 値とその個数を部分木ごとに並列に返すイテレータ
+pub struct MultiAVLTreeParCountIter<'a, T: Clone> {
+    root: Option<NodeRef<T>>,
+    overflow: &'a OverflowCounts,
+}
+
+impl<T: Clone + Send + Sync> ParallelIterator for MultiAVLTreeParCountIter<'_, T> {
+    type Item = (T, usize);
+
+    fn drive_unindexed<C: UnindexedConsumer<Self::Item>>(self, consumer: C) -> C::Result {
+        let producer = SubtreeProducer { node: self.root, with_left: true, with_right: true, overflow: self.overflow };
+        bridge_unindexed(producer, consumer)
+    }
+}
+
+/*
+    nodeと、with_leftなら左の部分木、with_rightなら右の部分木を受け持つ
+    左の部分木と残り、またはnode自身と右の部分木に分けていく
+*/
+struct SubtreeProducer<'a, T: Clone> {
+    node: Option<NodeRef<T>>,
+    with_left: bool,
+    with_right: bool,
+    overflow: &'a OverflowCounts,
+}
+
+impl<T: Clone + Send + Sync> UnindexedProducer for SubtreeProducer<'_, T> {
+    type Item = (T, usize);
+
+    fn split(self) -> (Self, Option<Self>) {
+        let node = match &self.node {
+            Some(v) => Rc::clone(v),
+            None => return (self, None),
+        };
+        let (left, right) = {
+            let n = node.borrow();
+            (n.left.as_ref().map(Rc::clone), n.right.as_ref().map(Rc::clone))
+        };
+
+        let overflow = self.overflow;
+        let whole = |v| SubtreeProducer { node: Some(v), with_left: true, with_right: true, overflow };
+        match (left, right) {
+            (Some(l), _) if self.with_left => {
+                let rest = SubtreeProducer { node: Some(node), with_left: false, with_right: self.with_right, overflow };
+                (whole(l), Some(rest))
+            },
+            (_, Some(r)) if self.with_right => {
+                let itself = SubtreeProducer { node: Some(node), with_left: false, with_right: false, overflow };
+                (itself, Some(whole(r)))
+            },
+            _ => (self, None),
+        }
+    }
+
+    fn fold_with<F: Folder<Self::Item>>(self, folder: F) -> F {
+        let node = match &self.node {
+            Some(v) => v,
+            None => return folder,
+        };
+        let (left, right) = {
+            let n = node.borrow();
+            (n.left.as_ref().map(Rc::clone), n.right.as_ref().map(Rc::clone))
+        };
+
+        let mut folder = folder;
+        if let (true, Some(l)) = (self.with_left, &left) {
+            folder = fold_subtree(l, folder, self.overflow);
+        }
+        if folder.full() {
+            return folder;
+        }
+        folder = folder.consume( (node.borrow().data.clone(), self.overflow.get(node)) );
+        if let (true, Some(r)) = (self.with_right, &right) {
+            folder = fold_subtree(r, folder, self.overflow);
+        }
+        folder
+    }
+}
+
+// 部分木を中順にたどってfolderに渡す
+fn fold_subtree<T: Clone, F: Folder<(T, usize)>>(node: &NodeRef<T>, folder: F, overflow: &OverflowCounts) -> F {
+    let (left, right) = {
+        let n = node.borrow();
+        (n.left.as_ref().map(Rc::clone), n.right.as_ref().map(Rc::clone))
+    };
+
+    let mut folder = folder;
+    if let Some(l) = &left {
+        folder = fold_subtree(l, folder, overflow);
+    }
+    if folder.full() {
+        return folder;
+    }
+    folder = folder.consume( (node.borrow().data.clone(), overflow.get(node)) );
+    if let Some(r) = &right {
+        folder = fold_subtree(r, folder, overflow);
+    }
+    folder
+}
@@ -0,0 +1,53 @@
+use alloc::string::String;
+use core::fmt::Write;
+
+use super::{BalancePolicy, MultiAVL, NodeRef, ValidationError};
+
+// 変更のたびに木を検証し、壊れていれば木の形を出力してpanicする
+impl<T: Ord + Clone, P: BalancePolicy> MultiAVL<T, P> {
+    pub(super) fn paranoid_check(&self, operation: &str) {
+        if let Err(e) = self.validate() {
+            self.paranoid_panic(operation, e);
+        }
+    }
+
+    fn paranoid_panic(&self, operation: &str, error: ValidationError<T>) -> ! {
+        let mut out = String::new();
+        writeln!(out, "invariant violated after {}: {}", operation, error.kind()).unwrap();
+        writeln!(out, "size = {}", self.size).unwrap();
+        match &self.root {
+            Some(v) => self.dump_node(v, 0, error.node_value(), &mut out),
+            None => out.push_str("(empty)\n"),
+        }
+        panic!("{}", out);
+    }
+
+    // 右の子を上にして横向きに出力する 違反が見つかったノードに印をつける
+    fn dump_node(&self, node: &NodeRef<T>, depth: usize, target: Option<&T>, out: &mut String) {
+        let n = node.borrow();
+        if let Some(v) = &n.right {
+            self.dump_node(v, depth + 1, target, out);
+        }
+
+        let mark = if target == Some(&n.data) { " <--" } else { "" };
+        let (name, attribute) = P::node_attribute(node);
+        writeln!(out, "{:indent$}(height {}, {} {}, count {}){}",
+            "", n.height(), name, attribute, self.overflow.get(node), mark, indent = depth * 4).unwrap();
+
+        if let Some(v) = &n.left {
+            self.dump_node(v, depth + 1, target, out);
+        }
+    }
+
+    /*
+        どの平衡化の方法でも、rotate_left / rotate_right の直後に確認する
+        リバランスの途中なので、バランス係数や色とsizeはまだ正しくない
+        順序と親子関係だけを見て、平衡の条件は操作の最後のparanoid_checkで確かめる
+    */
+    pub(super) fn paranoid_check_rotation(&self) {
+        let result = self.validate_order().and_then(|_| self.validate_relation());
+        if let Err(e) = result {
+            self.paranoid_panic("rotate", e);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use core::borrow;
+
+use super::{BalancePolicy, MultiAVL, NodeRef, OverflowCounts};
+
+fn subtree_len<T: Clone>(node: &Option<NodeRef<T>>) -> usize {
+    node.as_ref().map_or(0, |v| v.borrow().subtree_len)
+}
+
+fn subtree_distinct<T: Clone>(node: &Option<NodeRef<T>>) -> usize {
+    node.as_ref().map_or(0, |v| v.borrow().subtree_distinct)
+}
+
+// 子の値が正しいときに、nodeの部分木の要素数とノード数を計算し直す
+pub(super) fn update_subtree_counts<T: Clone>(node: &NodeRef<T>, overflow: &OverflowCounts) {
+    let (len, distinct) = {
+        let n = node.borrow();
+        (
+            overflow.get(node) + subtree_len(&n.left) + subtree_len(&n.right),
+            1 + subtree_distinct(&n.left) + subtree_distinct(&n.right),
+        )
+    };
+    let mut n = node.borrow_mut();
+    n.subtree_len = len;
+    n.subtree_distinct = distinct;
+}
+
+impl<T: Ord + Clone, P: BalancePolicy> MultiAVL<T, P> {
+    // 昇順で(0始まりの)i番目の要素 同じ値は個数だけ並べて数える
+    pub fn nth(&self, i: usize) -> Option<T> {
+        (i < self.size).then(|| self.nth_element(i))
+    }
+
+    // valueより小さい要素の数
+    pub fn rank<Q>(&self, value: &Q) -> usize
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        let mut rank = 0;
+        let mut now = self.root.clone();
+        while let Some(node) = now {
+            let n = node.borrow();
+            now = if *value <= *n.key() {
+                n.left.clone()
+            } else {
+                rank += subtree_len(&n.left) + self.overflow.get(&node);
+                n.right.clone()
+            };
+        }
+        rank
+    }
+
+    pub(super) fn nth_element(&self, mut i: usize) -> T {
+        debug_assert!(i < self.size);
+        let mut now = self.root.clone();
+        while let Some(node) = now {
+            let n = node.borrow();
+            let left = subtree_len(&n.left);
+            if i < left {
+                now = n.left.clone();
+                continue;
+            }
+            i -= left;
+            let count = self.overflow.get(&node);
+            if i < count {
+                return n.data.clone();
+            }
+            i -= count;
+            now = n.right.clone();
+        }
+        unreachable!("nth_element: subtree counts are broken")
+    }
+
+    // 昇順でi番目の値 同じ値は１つとして数える
+    #[cfg(feature = "rand")]
+    pub(super) fn nth_node(&self, mut i: usize) -> T {
+        debug_assert!(i < self.distinct);
+        let mut now = self.root.clone();
+        while let Some(node) = now {
+            let n = node.borrow();
+            let left = subtree_distinct(&n.left);
+            if i < left {
+                now = n.left.clone();
+                continue;
+            }
+            if i == left {
+                return n.data.clone();
+            }
+            i -= left + 1;
+            now = n.right.clone();
+        }
+        unreachable!("nth_node: subtree counts are broken")
+    }
+}
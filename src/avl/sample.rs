@@ -0,0 +1,45 @@
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+use rand::Rng;
+
+use super::{BalancePolicy, MultiAVL};
+
+/*
+    0..nから異なるk個の添字を一様に選ぶ (Floydのアルゴリズム)
+    k >= nなら全部を返す
+*/
+fn distinct_indices<R: Rng + ?Sized>(rng: &mut R, n: usize, k: usize) -> BTreeSet<usize> {
+    let mut chosen = BTreeSet::new();
+    for j in n - k.min(n)..n {
+        let t = rng.gen_range(0..=j);
+        if !chosen.insert(t) {
+            chosen.insert(j);
+        }
+    }
+    chosen
+}
+
+impl<T: Ord + Clone, P: BalancePolicy> MultiAVL<T, P> {
+    // 要素を１つ一様に選ぶ 個数が多い値ほど選ばれやすい
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        Some( self.nth_element(rng.gen_range(0..self.size)) )
+    }
+
+    // 重複を許さずにk個の要素を選び昇順に返す 同じ値は個数まで選ばれうる
+    pub fn sample_multiple<R: Rng + ?Sized>(&self, rng: &mut R, k: usize) -> Vec<T> {
+        distinct_indices(rng, self.size, k).into_iter()
+            .map(|i| self.nth_element(i))
+            .collect()
+    }
+
+    // 個数に関係なく、異なる値をk個一様に選び昇順に返す
+    pub fn sample_distinct<R: Rng + ?Sized>(&self, rng: &mut R, k: usize) -> Vec<T> {
+        distinct_indices(rng, self.distinct, k).into_iter()
+            .map(|i| self.nth_node(i))
+            .collect()
+    }
+}
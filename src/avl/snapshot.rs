@@ -0,0 +1,318 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use std::io::{self, Read, Write};
+
+use super::{BalancePolicy, MultiAVL};
+
+/*
+    スナップショットの形式 数値はすべてリトルエンディアン
+    MAGIC (4バイト) | VERSION (1バイト) | 値の型のタグ (1バイト) | 値の種類数 (varint)
+    | (値, 個数 (varint)) を昇順に種類数だけ | ここまでのCRC-32 (4バイト)
+*/
+const MAGIC: [u8; 4] = *b"MAVL";
+const VERSION: u8 = 1;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    // 先頭がMAGICでない
+    BadMagic,
+    UnsupportedVersion(u8),
+    // 書き込んだときと読み込むときで値の型が違う
+    TypeMismatch { expected: u8, found: u8 },
+    // 値や個数を読み取れない
+    Corrupt(&'static str),
+    // index番目の値が前の値以下
+    Unsorted { index: usize },
+    ChecksumMismatch { stored: u32, actual: u32 },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "io error: {}", e),
+            SnapshotError::BadMagic => write!(f, "not a snapshot"),
+            SnapshotError::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {}", v),
+            SnapshotError::TypeMismatch { expected, found } => {
+                write!(f, "snapshot has element type {} but {} was expected", found, expected)
+            },
+            SnapshotError::Corrupt(reason) => write!(f, "corrupt snapshot: {}", reason),
+            SnapshotError::Unsorted { index } => write!(f, "element {} is not greater than the previous one", index),
+            SnapshotError::ChecksumMismatch { stored, actual } => {
+                write!(f, "checksum is {:08x} but should be {:08x}", stored, actual)
+            },
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SnapshotError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+// スナップショットに書き込む値の形式
+pub trait SnapshotCodec: Sized {
+    // 型ごとに異なる値にして、違う型として読み込むのを防ぐ
+    const TYPE_TAG: u8;
+    fn encode(&self, writer: &mut dyn Write) -> io::Result<()>;
+    fn decode(reader: &mut dyn Read) -> Result<Self, SnapshotError>;
+}
+
+// 符号なし整数はLEB128、符号付き整数はzigzagにしてからLEB128で書く
+macro_rules! impl_unsigned_codec {
+    ($($t:ty => $tag:expr),*) => {$(
+        impl SnapshotCodec for $t {
+            const TYPE_TAG: u8 = $tag;
+
+            fn encode(&self, writer: &mut dyn Write) -> io::Result<()> {
+                write_varint(writer, *self as u64)
+            }
+
+            fn decode(reader: &mut dyn Read) -> Result<Self, SnapshotError> {
+                <$t>::try_from(read_varint(reader)?).map_err(|_| SnapshotError::Corrupt("value out of range"))
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_signed_codec {
+    ($($t:ty => $tag:expr),*) => {$(
+        impl SnapshotCodec for $t {
+            const TYPE_TAG: u8 = $tag;
+
+            fn encode(&self, writer: &mut dyn Write) -> io::Result<()> {
+                let v = *self as i64;
+                write_varint(writer, ((v << 1) ^ (v >> 63)) as u64)
+            }
+
+            fn decode(reader: &mut dyn Read) -> Result<Self, SnapshotError> {
+                let v = read_varint(reader)?;
+                let v = (v >> 1) as i64 ^ -((v & 1) as i64);
+                <$t>::try_from(v).map_err(|_| SnapshotError::Corrupt("value out of range"))
+            }
+        }
+    )*};
+}
+
+impl_unsigned_codec!(u8 => 1, u16 => 2, u32 => 3, u64 => 4, usize => 5);
+impl_signed_codec!(i8 => 6, i16 => 7, i32 => 8, i64 => 9, isize => 10);
+
+// 長さとバイト列
+impl SnapshotCodec for Vec<u8> {
+    const TYPE_TAG: u8 = 16;
+
+    fn encode(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write_varint(writer, self.len() as u64)?;
+        writer.write_all(self)
+    }
+
+    fn decode(reader: &mut dyn Read) -> Result<Self, SnapshotError> {
+        let len = read_varint(reader)?;
+        let mut bytes = Vec::new();
+        reader.take(len).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != len {
+            return Err( io::Error::from(io::ErrorKind::UnexpectedEof).into() );
+        }
+        Ok(bytes)
+    }
+}
+
+impl SnapshotCodec for String {
+    const TYPE_TAG: u8 = 17;
+
+    fn encode(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write_varint(writer, self.len() as u64)?;
+        writer.write_all(self.as_bytes())
+    }
+
+    fn decode(reader: &mut dyn Read) -> Result<Self, SnapshotError> {
+        String::from_utf8(Vec::<u8>::decode(reader)?).map_err(|_| SnapshotError::Corrupt("invalid utf-8"))
+    }
+}
+
+pub(super) fn write_varint(writer: &mut dyn Write, mut value: u64) -> io::Result<()> {
+    let mut buf = [0; 10];
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[len] = byte;
+            len += 1;
+            break;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+    writer.write_all(&buf[..len])
+}
+
+pub(super) fn read_varint(reader: &mut dyn Read) -> Result<u64, SnapshotError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        let bits = (byte[0] & 0x7f) as u64;
+        if shift == 63 && bits > 1 {
+            return Err( SnapshotError::Corrupt("varint overflow") );
+        }
+        value |= bits << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err( SnapshotError::Corrupt("varint overflow") )
+}
+
+// CRC-32 (IEEE)
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+#[derive(Clone, Copy)]
+pub(super) struct Crc32(u32);
+
+impl Crc32 {
+    pub(super) fn new() -> Self {
+        Crc32(0xffff_ffff)
+    }
+
+    pub(super) fn update(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 = CRC_TABLE[((self.0 ^ *b as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub(super) fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+// 通ったバイト列のCRCを計算する
+struct ChecksumWriter<W> {
+    inner: W,
+    crc: Crc32,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct ChecksumReader<R> {
+    inner: R,
+    crc: Crc32,
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<T: Ord + Clone + SnapshotCodec, P: BalancePolicy> MultiAVL<T, P> {
+    // 小さな書き込みを繰り返すので、ファイルならBufWriterを渡す
+    pub fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut writer = ChecksumWriter { inner: writer, crc: Crc32::new() };
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[VERSION, T::TYPE_TAG])?;
+        write_varint(&mut writer, self.distinct as u64)?;
+        self.try_for_each_count(|value, count| {
+            value.encode(&mut writer)?;
+            write_varint(&mut writer, count as u64)
+        })?;
+
+        let checksum = writer.crc.finish();
+        writer.inner.write_all(&checksum.to_le_bytes())?;
+        writer.inner.flush()
+    }
+
+    // write_toで書いたものから線形時間で木を作る CRCが合ってから中身を確認する
+    pub fn read_from<R: Read>(reader: R) -> Result<MultiAVL<T, P>, SnapshotError> {
+        let mut reader = ChecksumReader { inner: reader, crc: Crc32::new() };
+        let mut header = [0; 6];
+        reader.read_exact(&mut header)?;
+        if header[..4] != MAGIC {
+            return Err( SnapshotError::BadMagic );
+        }
+        if header[4] != VERSION {
+            return Err( SnapshotError::UnsupportedVersion(header[4]) );
+        }
+        if header[5] != T::TYPE_TAG {
+            return Err( SnapshotError::TypeMismatch { expected: T::TYPE_TAG, found: header[5] } );
+        }
+
+        let distinct = read_varint(&mut reader)?;
+        let mut counts = Vec::new();
+        for _ in 0..distinct {
+            let value = T::decode(&mut reader)?;
+            let count = usize::try_from(read_varint(&mut reader)?).map_err(|_| SnapshotError::Corrupt("count out of range"))?;
+            counts.push( (value, count) );
+        }
+
+        let actual = reader.crc.finish();
+        let mut stored = [0; 4];
+        reader.inner.read_exact(&mut stored)?;
+        let stored = u32::from_le_bytes(stored);
+        if stored != actual {
+            return Err( SnapshotError::ChecksumMismatch { stored, actual } );
+        }
+
+        let mut size = 0usize;
+        for (index, (value, count)) in counts.iter().enumerate() {
+            if index > 0 && counts[index - 1].0 >= *value {
+                return Err( SnapshotError::Unsorted { index } );
+            }
+            if *count == 0 {
+                return Err( SnapshotError::Corrupt("zero count") );
+            }
+            size = size.checked_add(*count).ok_or(SnapshotError::Corrupt("total count overflow"))?;
+        }
+
+        let mut tree = Self::with_policy();
+        let root = (!counts.is_empty()).then(|| Self::build_subtree(&counts, 0, counts.len(), &tree.overflow));
+        tree.set_built_root(root, &counts);
+        Ok(tree)
+    }
+}
+
+// 検証のテスト用に、書き換えたスナップショットのCRCを付け直す
+#[cfg(test)]
+pub(crate) fn fix_checksum(bytes: &mut [u8]) {
+    let len = bytes.len() - 4;
+    let mut crc = Crc32::new();
+    crc.update(&bytes[..len]);
+    bytes[len..].copy_from_slice(&crc.finish().to_le_bytes());
+}
@@ -0,0 +1,154 @@
+#[cfg(not(feature = "rayon"))]
+use core::cell::Cell;
+#[cfg(feature = "rayon")]
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::mem::size_of;
+
+use super::{BalancePolicy, MultiAVL, Node, NodeRef, Operation, RefCell};
+
+// rayon機能では複数のスレッドから同時に探索するのでアトミックにする
+#[cfg(not(feature = "rayon"))]
+type Count = Cell<u64>;
+#[cfg(feature = "rayon")]
+type Count = AtomicU64;
+
+// 操作の回数 探索は&selfで行うので内部可変性を持たせる
+#[derive(Default)]
+pub(super) struct Counters {
+    inserts: Count,
+    erases: Count,
+    searches: Count,
+    comparisons: Count,
+    rotations: Count,
+    double_rotations: Count,
+}
+
+#[cfg(not(feature = "rayon"))]
+fn increment(count: &Count) {
+    count.set(count.get() + 1);
+}
+
+#[cfg(not(feature = "rayon"))]
+fn load(count: &Count) -> u64 {
+    count.get()
+}
+
+#[cfg(feature = "rayon")]
+fn increment(count: &Count) {
+    count.fetch_add(1, Ordering::Relaxed);
+}
+
+#[cfg(feature = "rayon")]
+fn load(count: &Count) -> u64 {
+    count.load(Ordering::Relaxed)
+}
+
+impl Counters {
+    pub(super) fn count_insert(&self) {
+        increment(&self.inserts);
+    }
+
+    pub(super) fn count_erase(&self) {
+        increment(&self.erases);
+    }
+
+    pub(super) fn count_search(&self) {
+        increment(&self.searches);
+    }
+
+    pub(super) fn count_comparison(&self) {
+        increment(&self.comparisons);
+    }
+
+    pub(super) fn count_rotation(&self) {
+        increment(&self.rotations);
+    }
+
+    pub(super) fn count_double_rotation(&self) {
+        increment(&self.double_rotations);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    // 要素数(重複を含む)
+    pub len: usize,
+    // ノード数(重複を含まない)
+    pub distinct: usize,
+    // 根から一番深いノードまでの段数 空なら0、根だけなら1
+    // to_ascii や to_dot の height は辺の数で数えて葉を0とするので、根のそれより1大きい
+    pub height: usize,
+    // 根の深さを0とした各ノードの深さの平均
+    pub average_depth: f64,
+    // 二重回転に含まれない回転の回数
+    pub single_rotations: u64,
+    pub double_rotations: u64,
+    // 個数の増減を含めた挿入・削除の回数
+    pub inserts: u64,
+    pub erases: u64,
+    // 値の探索の回数と、その中で行った比較の回数
+    pub searches: u64,
+    pub comparisons: u64,
+    // ノードと操作ログ、大きな個数の表が使うヒープの概算 T自身が持つヒープは含まない
+    pub heap_bytes: usize,
+}
+
+impl Stats {
+    pub fn comparisons_per_search(&self) -> f64 {
+        if self.searches == 0 {
+            return 0.0;
+        }
+        self.comparisons as f64 / self.searches as f64
+    }
+}
+
+impl<T: Ord + Clone, P: BalancePolicy> MultiAVL<T, P> {
+    pub fn stats(&self) -> Stats {
+        let mut distinct = 0;
+        let mut total_depth = 0;
+        let mut height = 0;
+        if let Some(v) = &self.root {
+            Self::dfs_stats(v, 0, &mut distinct, &mut total_depth, &mut height);
+        }
+
+        let rotations = load(&self.counters.rotations);
+        let double_rotations = load(&self.counters.double_rotations);
+        let journal_bytes = self.journal.as_ref().map_or(0, |v| {
+            v.operations.capacity() * size_of::<Operation<T>>() + v.marks.capacity() * size_of::<(usize, usize)>()
+        });
+        // 個数が大きいノードはBTreeMapに(アドレス, 個数)を持つ
+        let overflow_bytes = self.overflow.len() * 2 * size_of::<usize>();
+        Stats {
+            len: self.size,
+            distinct,
+            height,
+            average_depth: if distinct == 0 { 0.0 } else { total_depth as f64 / distinct as f64 },
+            single_rotations: rotations - 2 * double_rotations,
+            double_rotations,
+            inserts: load(&self.counters.inserts),
+            erases: load(&self.counters.erases),
+            searches: load(&self.counters.searches),
+            comparisons: load(&self.counters.comparisons),
+            heap_bytes: distinct * Self::node_allocation_size() + journal_bytes + overflow_bytes,
+        }
+    }
+
+    // 操作の回数を0に戻す
+    pub fn reset_stats(&mut self) {
+        self.counters = Counters::default();
+    }
+
+    // Rcの参照カウント２つとRefCellに包まれたノード
+    fn node_allocation_size() -> usize {
+        2 * size_of::<usize>() + size_of::<RefCell<Node<T>>>()
+    }
+
+    fn dfs_stats(node: &NodeRef<T>, depth: usize, distinct: &mut usize, total_depth: &mut usize, height: &mut usize) {
+        *distinct += 1;
+        *total_depth += depth;
+        *height = (*height).max(depth + 1);
+        for child in [&node.borrow().left, &node.borrow().right].into_iter().flatten() {
+            Self::dfs_stats(child, depth + 1, distinct, total_depth, height);
+        }
+    }
+}
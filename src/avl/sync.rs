@@ -0,0 +1,34 @@
+/*
+    ノードの共有と内部可変性
+    rayon機能では木を複数のスレッドから読むので、RcとRefCellの代わりにArcとRwLockを使う
+    どちらも同じ名前と同じメソッドで使えるようにしておく
+*/
+#[cfg(not(feature = "rayon"))]
+pub(super) use alloc::rc::{Rc, Weak};
+#[cfg(not(feature = "rayon"))]
+pub(super) use core::cell::RefCell;
+
+#[cfg(feature = "rayon")]
+pub(super) use std::sync::{Arc as Rc, Weak};
+
+#[cfg(feature = "rayon")]
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+#[cfg(feature = "rayon")]
+#[derive(Default)]
+pub(super) struct RefCell<T>(RwLock<T>);
+
+#[cfg(feature = "rayon")]
+impl<T> RefCell<T> {
+    pub(super) fn new(value: T) -> Self {
+        Self( RwLock::new(value) )
+    }
+
+    pub(super) fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        self.0.read().unwrap()
+    }
+
+    pub(super) fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+        self.0.write().unwrap()
+    }
+}
@@ -0,0 +1,132 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use super::{BalancePolicy, MultiAVL, NodeSide};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceSide {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DoubleRotationKind {
+    // 左の子を左回転してから右回転
+    LeftRight,
+    // 右の子を右回転してから左回転
+    RightLeft,
+}
+
+// 木の形を変える操作 ノードはその時点での値で表す
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceEvent<T> {
+    Link { parent: T, child: T, side: TraceSide },
+    Unlink { parent: T, child: T, side: TraceSide },
+    Root { node: Option<T> },
+    RotateLeft { node: T },
+    RotateRight { node: T },
+    // この後に続く２回の回転が１組の二重回転であることを表す
+    DoubleRotation { node: T, kind: DoubleRotationKind },
+    // ２つの子を持つノードの削除で、左部分木の最大ノードと位置を入れ替える
+    Swap { node: T, predecessor: T },
+}
+
+impl From<&NodeSide> for TraceSide {
+    fn from(side: &NodeSide) -> Self {
+        match side {
+            NodeSide::Left => TraceSide::Left,
+            NodeSide::Right => TraceSide::Right,
+        }
+    }
+}
+
+impl TraceSide {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TraceSide::Left => "left",
+            TraceSide::Right => "right",
+        }
+    }
+}
+
+impl DoubleRotationKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DoubleRotationKind::LeftRight => "left_right",
+            DoubleRotationKind::RightLeft => "right_left",
+        }
+    }
+}
+
+// 値はDebugの出力を文字列として書き出す
+impl<T: Debug> TraceEvent<T> {
+    pub fn to_json(&self) -> String {
+        let value = |v: &T| json_string(&format!("{:?}", v));
+        match self {
+            TraceEvent::Link { parent, child, side } => format!(
+                r#"{{"event":"link","parent":{},"child":{},"side":"{}"}}"#, value(parent), value(child), side.as_str()),
+            TraceEvent::Unlink { parent, child, side } => format!(
+                r#"{{"event":"unlink","parent":{},"child":{},"side":"{}"}}"#, value(parent), value(child), side.as_str()),
+            TraceEvent::Root { node } => format!(
+                r#"{{"event":"root","node":{}}}"#, node.as_ref().map_or(String::from("null"), value)),
+            TraceEvent::RotateLeft { node } => format!(r#"{{"event":"rotate_left","node":{}}}"#, value(node)),
+            TraceEvent::RotateRight { node } => format!(r#"{{"event":"rotate_right","node":{}}}"#, value(node)),
+            TraceEvent::DoubleRotation { node, kind } => format!(
+                r#"{{"event":"double_rotation","node":{},"kind":"{}"}}"#, value(node), kind.as_str()),
+            TraceEvent::Swap { node, predecessor } => format!(
+                r#"{{"event":"swap","node":{},"predecessor":{}}}"#, value(node), value(predecessor)),
+        }
+    }
+}
+
+// １行に１イベントのJSON Lines形式
+pub fn to_json_lines<T: Debug>(events: &[TraceEvent<T>]) -> String {
+    let mut out = String::new();
+    for event in events {
+        out.push_str(&event.to_json());
+        out.push('\n');
+    }
+    out
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl<T: Ord + Clone, P: BalancePolicy> MultiAVL<T, P> {
+    // これ以降の木の形を変える操作を記録する
+    pub fn start_trace(&mut self) {
+        self.trace = Some( Vec::new() );
+    }
+
+    // 記録したイベントを取り出す 記録は続ける
+    pub fn take_trace(&mut self) -> Vec<TraceEvent<T>> {
+        self.trace.as_mut().map(core::mem::take).unwrap_or_default()
+    }
+
+    // 記録をやめて、残っているイベントを返す
+    pub fn stop_trace(&mut self) -> Vec<TraceEvent<T>> {
+        self.trace.take().unwrap_or_default()
+    }
+
+    pub(super) fn record_trace<F: FnOnce() -> TraceEvent<T>>(&mut self, event: F) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(event());
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use core::borrow;
+
+use super::{next_node, prev_node, BalancePolicy, MultiAVL, Node, NodeRef, Operation, Rc};
+
+impl<T: Ord + Clone, P: BalancePolicy> MultiAVL<T, P> {
+    // oldを１つ取り除いてnewを入れる oldがなければ何もせずfalseを返す
+    pub fn update<Q>(&mut self, old: &Q, new: T) -> bool
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        match self.find_node(old) {
+            Some(node) => {
+                self.update_node(&node, new);
+                true
+            },
+            None => false,
+        }
+    }
+
+    // oldを１つ取り出してfで書き換え、入れ直す oldがなければfを呼ばずにfalseを返す
+    pub fn modify<Q, F>(&mut self, old: &Q, f: F) -> bool
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized, F: FnOnce(&mut T)
+    {
+        let node = match self.find_node(old) {
+            Some(v) => v,
+            None => return false,
+        };
+        let mut value = node.borrow().data.clone();
+        f(&mut value);
+        self.update_node(&node, value);
+        true
+    }
+
+    /*
+        個数が1で、newが前後の値の間に収まるなら、ノードの値を書き換えるだけで済ませる
+        収まらなければノードを外し、同じ割り当てを新しい位置に入れ直す
+    */
+    fn update_node(&mut self, node: &NodeRef<T>, new: T) {
+        self.record(|| Operation::Erase(node.borrow().data.clone(), 1));
+        self.record(|| Operation::Insert(new.clone(), 1));
+
+        if self.overflow.get(node) > 1 {
+            self.decrease_count(node, 1);
+            match self.locate(&new) {
+                Ok(v) => self.increase_count(&v, 1),
+                Err(position) => { self.insert_at(position, new, 1); },
+            }
+            return;
+        }
+
+        self.counters.count_erase();
+        if self.stays_in_place(node, &new) {
+            self.counters.count_insert();
+            node.borrow_mut().data = new;
+            self.augment_to_root(node);
+        } else {
+            let removed = self.erase_node(node).unwrap();
+            match self.locate(&new) {
+                Ok(v) => self.increase_count(&v, 1),
+                // 外れたノードをイテレータが指していなければ、中身を作り直して使う
+                Err(position) if Rc::weak_count(&removed) == 0 => {
+                    *removed.borrow_mut() = Node::new(new, None);
+                    self.insert_node_at(position, removed, 1);
+                },
+                Err(position) => { self.insert_at(position, new, 1); },
+            }
+        }
+
+        #[cfg(feature = "paranoid")]
+        self.paranoid_check("update");
+    }
+
+    // 前の値 < new < 次の値 なら、nodeの値をnewにしても順序が保たれる
+    fn stays_in_place(&self, node: &NodeRef<T>, new: &T) -> bool {
+        if *new == node.borrow().data {
+            return true;
+        }
+        let after_prev = prev_node(node).is_none_or(|v| v.borrow().data < *new);
+        let before_next = next_node(node).is_none_or(|v| *new < v.borrow().data);
+        after_prev && before_next
+    }
+}
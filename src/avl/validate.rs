@@ -0,0 +1,279 @@
+use core::fmt;
+
+use super::{BalancePolicy, MultiAVL, NodeRef, Rc};
+
+// 木の不変条件の違反 valueは違反が見つかったノードの値
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError<T> {
+    // left < node < right が成り立たない
+    Order { value: T },
+    // 子のparentが親を指していない
+    Parent { value: T },
+    // 根がparentを持っている
+    RootParent { value: T },
+    // 個数が0のノードが残っている
+    EmptyNode { value: T },
+    // 保存されているバランス係数が実際と異なる
+    BalanceFactor { value: T, stored: i32, actual: i32 },
+    // 左右の高さの差が1を超えている
+    Balance { value: T, balance_factor: i32 },
+    // 赤黒木で根が赤か、赤のノードの親が赤
+    Color { value: T },
+    // 赤黒木で左右の部分木の黒の高さが異なる
+    BlackHeight { value: T, left: usize, right: usize },
+    // rank機能で、ノードが持つ部分木の(要素数, ノード数)が実際と異なる
+    SubtreeCounts { value: T, stored: (usize, usize), actual: (usize, usize) },
+    // sizeが各ノードの個数の合計と異なる
+    Size { stored: usize, actual: usize },
+    // distinctがノード数と異なる
+    Distinct { stored: usize, actual: usize },
+    // min_nodeが最小のノードを指していない
+    MinNode { cached: Option<T>, actual: Option<T> },
+    // max_nodeが最大のノードを指していない
+    MaxNode { cached: Option<T>, actual: Option<T> },
+}
+
+impl<T: fmt::Debug> fmt::Display for ValidationError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::Order { value } => write!(f, "order is not correct at {:?}", value),
+            ValidationError::Parent { value } => write!(f, "parent link of {:?} is not correct", value),
+            ValidationError::RootParent { value } => write!(f, "root {:?} has a parent", value),
+            ValidationError::EmptyNode { value } => write!(f, "node {:?} has no elements", value),
+            ValidationError::BalanceFactor { value, stored, actual } => {
+                write!(f, "balance factor of {:?} is {} but should be {}", value, stored, actual)
+            },
+            ValidationError::Balance { value, balance_factor } => {
+                write!(f, "node {:?} is not balanced (balance factor {})", value, balance_factor)
+            },
+            ValidationError::Color { value } => write!(f, "color of {:?} is not correct", value),
+            ValidationError::BlackHeight { value, left, right } => {
+                write!(f, "black heights under {:?} differ (left {}, right {})", value, left, right)
+            },
+            ValidationError::SubtreeCounts { value, stored, actual } => {
+                write!(f, "subtree counts of {:?} are {:?} but should be {:?}", value, stored, actual)
+            },
+            ValidationError::Size { stored, actual } => write!(f, "size is {} but should be {}", stored, actual),
+            ValidationError::Distinct { stored, actual } => {
+                write!(f, "distinct length is {} but should be {}", stored, actual)
+            },
+            ValidationError::MinNode { cached, actual } => {
+                write!(f, "min node is {:?} but should be {:?}", cached, actual)
+            },
+            ValidationError::MaxNode { cached, actual } => {
+                write!(f, "max node is {:?} but should be {:?}", cached, actual)
+            },
+        }
+    }
+}
+
+impl<T: fmt::Debug> core::error::Error for ValidationError<T> {}
+
+impl<T> ValidationError<T> {
+    // 違反の種類
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ValidationError::Order { .. } => "order",
+            ValidationError::Parent { .. } => "parent link",
+            ValidationError::RootParent { .. } => "root parent",
+            ValidationError::EmptyNode { .. } => "empty node",
+            ValidationError::BalanceFactor { .. } => "balance factor",
+            ValidationError::Balance { .. } => "balance",
+            ValidationError::Color { .. } => "color",
+            ValidationError::BlackHeight { .. } => "black height",
+            ValidationError::SubtreeCounts { .. } => "subtree counts",
+            ValidationError::Size { .. } => "size",
+            ValidationError::Distinct { .. } => "distinct length",
+            ValidationError::MinNode { .. } => "min node",
+            ValidationError::MaxNode { .. } => "max node",
+        }
+    }
+
+    // 違反が見つかったノードの値 木全体の違反ならNone
+    pub fn node_value(&self) -> Option<&T> {
+        match self {
+            ValidationError::Order { value }
+            | ValidationError::Parent { value }
+            | ValidationError::RootParent { value }
+            | ValidationError::EmptyNode { value }
+            | ValidationError::BalanceFactor { value, .. }
+            | ValidationError::Balance { value, .. }
+            | ValidationError::Color { value }
+            | ValidationError::BlackHeight { value, .. }
+            | ValidationError::SubtreeCounts { value, .. } => Some(value),
+            ValidationError::Size { .. } | ValidationError::Distinct { .. } => None,
+            ValidationError::MinNode { actual, .. } | ValidationError::MaxNode { actual, .. } => actual.as_ref(),
+        }
+    }
+}
+
+impl<T: Ord + Clone, P: BalancePolicy> MultiAVL<T, P> {
+    pub fn validate(&self) -> Result<(), ValidationError<T>> {
+        self.validate_size()?;
+        self.validate_order()?;
+        self.validate_relation()?;
+        P::validate_balance(self)?;
+        self.validate_min_max()?;
+        Ok(())
+    }
+
+    // 木の要素数とノード数を確認する
+    fn validate_size(&self) -> Result<(), ValidationError<T>> {
+        let (mut size, mut distinct) = (0, 0);
+        if let Some(v) = &self.root {
+            (size, distinct) = self.dfs_validate_size(v)?;
+        }
+        if self.size != size {
+            return Err( ValidationError::Size { stored: self.size, actual: size } );
+        }
+        if self.distinct != distinct {
+            return Err( ValidationError::Distinct { stored: self.distinct, actual: distinct } );
+        }
+        Ok(())
+    }
+
+    // (要素数, ノード数)を返す
+    fn dfs_validate_size(&self, node: &NodeRef<T>) -> Result<(usize, usize), ValidationError<T>> {
+        let mut cnt = self.overflow.get(node);
+        let mut nodes = 1;
+        if cnt == 0 {
+            return Err( ValidationError::EmptyNode { value: node.borrow().data.clone() } );
+        }
+        for child in [&node.borrow().left, &node.borrow().right].into_iter().flatten() {
+            let (child_cnt, child_nodes) = self.dfs_validate_size(child)?;
+            cnt += child_cnt;
+            nodes += child_nodes;
+        }
+
+        #[cfg(feature = "rank")]
+        {
+            let n = node.borrow();
+            if (n.subtree_len, n.subtree_distinct) != (cnt, nodes) {
+                return Err( ValidationError::SubtreeCounts {
+                    value: n.data.clone(),
+                    stored: (n.subtree_len, n.subtree_distinct),
+                    actual: (cnt, nodes),
+                } );
+            }
+        }
+
+        Ok((cnt, nodes))
+    }
+
+    /*
+        木の大小関係を確認する
+        left < node < right
+    */
+    pub(super) fn validate_order(&self) -> Result<(), ValidationError<T>> {
+        if let Some(v) = &self.root {
+            Self::dfs_validate_order(v, None, None)?;
+        }
+        Ok(())
+    }
+
+    fn dfs_validate_order(node: &NodeRef<T>, min_value: Option<&T>, max_value: Option<&T>) -> Result<(), ValidationError<T>> {
+        let n = node.borrow();
+        let out_of_range = max_value.is_some_and(|max| n.data >= *max) || min_value.is_some_and(|min| n.data <= *min);
+        if out_of_range {
+            return Err( ValidationError::Order { value: n.data.clone() } );
+        }
+
+        if let Some(left) = &n.left {
+            Self::dfs_validate_order(left, min_value, Some( &n.data ))?;
+        }
+        if let Some(right) = &n.right {
+            Self::dfs_validate_order(right, Some( &n.data ), max_value)?;
+        }
+
+        Ok(())
+    }
+
+    // ノードの親子関係を確認する
+    pub(super) fn validate_relation(&self) -> Result<(), ValidationError<T>> {
+        if let Some(v) = &self.root {
+            if v.borrow().parent.is_some() {
+                return Err( ValidationError::RootParent { value: v.borrow().data.clone() } );
+            }
+            Self::dfs_validate_relation(v)?;
+        }
+
+        Ok(())
+    }
+
+    fn check_relation(node: &NodeRef<T>, child: &NodeRef<T>) -> bool {
+        match child.borrow().parent.as_ref().and_then(|v| v.upgrade()) {
+            Some(v) => Rc::ptr_eq(node, &v), // 参照が正しいか
+            None => false, // 親要素が設定されていて、生きているか
+        }
+    }
+
+    fn dfs_validate_relation(node: &NodeRef<T>) -> Result<(), ValidationError<T>> {
+        for child in [&node.borrow().left, &node.borrow().right].into_iter().flatten() {
+            if !Self::check_relation(node, child) {
+                return Err( ValidationError::Parent { value: child.borrow().data.clone() } );
+            }
+            Self::dfs_validate_relation(child)?;
+        }
+
+        Ok(())
+    }
+
+    // キャッシュしている最大最小のノードを確認する
+    fn validate_min_max(&self) -> Result<(), ValidationError<T>> {
+        let value_of = |v: &Option<NodeRef<T>>| v.as_ref().map(|n| n.borrow().data.clone());
+        let same_node = |a: &Option<NodeRef<T>>, b: &Option<NodeRef<T>>| match (a, b) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+
+        let actual = self.find_min_node();
+        if !same_node(&self.min_node, &actual) {
+            return Err( ValidationError::MinNode { cached: value_of(&self.min_node), actual: value_of(&actual) } );
+        }
+        let actual = self.find_max_node();
+        if !same_node(&self.max_node, &actual) {
+            return Err( ValidationError::MaxNode { cached: value_of(&self.max_node), actual: value_of(&actual) } );
+        }
+        Ok(())
+    }
+}
+
+// テスト用関数
+#[cfg(test)]
+impl<T: Ord + Clone + fmt::Debug + 'static, P: BalancePolicy> MultiAVL<T, P> {
+    pub fn check_consistent(&self) -> Result<(), Box<dyn std::error::Error>> {
+        Ok( self.validate()? )
+    }
+
+    // 大きな個数の表にあるノードの数
+    pub fn overflow_len(&self) -> usize {
+        self.overflow.len()
+    }
+}
+
+// 検証のテスト用にわざと木を壊す
+#[cfg(test)]
+impl<T: Ord + Clone, P: BalancePolicy> MultiAVL<T, P> {
+    pub fn corrupt_size(&mut self, size: usize) {
+        self.size = size;
+    }
+
+    pub fn corrupt_min_node(&mut self) {
+        self.min_node = self.max_node.clone();
+    }
+
+    // AVL木ならバランス係数、赤黒木なら色
+    pub fn corrupt_root_balance_bits(&mut self, bits: u32) {
+        self.root.as_ref().unwrap().borrow_mut().set_balance_bits(bits);
+    }
+
+    // 根の左右の子を入れ替えて順序を壊す
+    pub fn corrupt_order(&mut self) {
+        let root = self.root.as_ref().unwrap();
+        let mut root = root.borrow_mut();
+        let left = root.left.take();
+        root.left = root.right.take();
+        root.right = left;
+    }
+}
@@ -0,0 +1,251 @@
+use core::borrow;
+use core::cmp::Ordering;
+
+use super::{Avl, BalancePolicy, MultiAVL, NodeRef, OccupiedEntry};
+
+/*
+    値ごとに重みを持つ多重集合 要素の重みは (値の重み) * (個数)
+    各ノードに部分木の重みの合計を持たせ、累積の重みでの探索を O(log n) で行う
+*/
+#[derive(Debug, Clone)]
+struct Weighted<T> {
+    value: T,
+    weight: u64,
+    // このノードを根とする部分木の重みの合計
+    sum: u64,
+}
+
+// 値だけで比べ、値で探せるようにする
+impl<T: Ord> Ord for Weighted<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.value.cmp(&other.value)
+    }
+}
+
+impl<T: Ord> PartialOrd for Weighted<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some( self.cmp(other) )
+    }
+}
+
+impl<T: Ord> PartialEq for Weighted<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T: Ord> Eq for Weighted<T> {}
+
+impl<T> borrow::Borrow<T> for Weighted<T> {
+    fn borrow(&self) -> &T {
+        &self.value
+    }
+}
+
+// 探すときは中の値のBorrowで比べる
+fn value_key<T: borrow::Borrow<Q>, Q: ?Sized>(weighted: &Weighted<T>) -> &Q {
+    weighted.value.borrow()
+}
+
+fn subtree_sum<T: Clone>(node: &Option<NodeRef<Weighted<T>>>) -> u64 {
+    node.as_ref().map_or(0, |v| v.borrow().data.sum)
+}
+
+fn update_sum<T: Clone>(node: &NodeRef<Weighted<T>>, count: usize) {
+    let sum = {
+        let n = node.borrow();
+        n.data.weight * count as u64 + subtree_sum(&n.left) + subtree_sum(&n.right)
+    };
+    node.borrow_mut().data.sum = sum;
+}
+
+pub struct WeightedMultiAVL<T, P = Avl>
+    where T: Ord + Clone, P: BalancePolicy
+{
+    tree: MultiAVL<Weighted<T>, P>,
+}
+
+impl<T: Ord + Clone, P: BalancePolicy> Default for WeightedMultiAVL<T, P> {
+    fn default() -> Self {
+        Self::with_policy()
+    }
+}
+
+impl<T: Ord + Clone> WeightedMultiAVL<T> {
+    pub fn new() -> WeightedMultiAVL<T> {
+        Self::with_policy()
+    }
+}
+
+impl<T: Ord + Clone, P: BalancePolicy> WeightedMultiAVL<T, P> {
+    pub fn with_policy() -> WeightedMultiAVL<T, P> {
+        let mut tree = MultiAVL::with_policy();
+        tree.set_augment(update_sum::<T>);
+        WeightedMultiAVL { tree }
+    }
+
+    pub fn size(&self) -> usize {
+        self.tree.size()
+    }
+
+    pub fn distinct_len(&self) -> usize {
+        self.tree.distinct_len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /*
+        同じ値の要素は同じ重みを持つ 既にある値を入れたときは、その値の重みをweightに置き換える
+        重みの合計がu64に収まらなければpanicする 挿入後の個数を返す
+    */
+    pub fn insert(&mut self, value: T, weight: u64) -> usize {
+        let count = self.tree.count(&value) as u64;
+        let old = self.weight(&value).unwrap_or(0);
+        let total = weight.checked_mul(count + 1).and_then(|v| (self.total_weight() - old * count).checked_add(v));
+        assert!(total.is_some(), "WeightedMultiAVL::insert: total weight overflow");
+
+        if let Some(node) = self.tree.find_node(&value) {
+            node.borrow_mut().data.weight = weight;
+        }
+        self.tree.insert(Weighted { value, weight, sum: weight })
+    }
+
+    // 値の重みを変える 値がなければfalseを返す
+    pub fn set_weight<Q>(&mut self, value: &Q, weight: u64) -> bool
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        let node = match self.find_node(value) {
+            Some(v) => v,
+            None => return false,
+        };
+        let count = self.tree.overflow.get(&node) as u64;
+        let old = node.borrow().data.weight;
+        let total = weight.checked_mul(count).and_then(|v| (self.total_weight() - old * count).checked_add(v));
+        assert!(total.is_some(), "WeightedMultiAVL::set_weight: total weight overflow");
+
+        node.borrow_mut().data.weight = weight;
+        self.tree.augment_to_root(&node);
+        true
+    }
+
+    // 値１つあたりの重み
+    pub fn weight<Q>(&self, value: &Q) -> Option<u64>
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        self.find_node(value).map(|v| v.borrow().data.weight)
+    }
+
+    pub fn erase<Q>(&mut self, value: &Q) -> bool
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        match self.find_node(value) {
+            Some(node) => OccupiedEntry { tree: &mut self.tree, node }.remove_n(1) == 1,
+            None => false,
+        }
+    }
+
+    pub fn count<Q>(&self, value: &Q) -> usize
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        self.find_node(value).map_or(0, |v| self.tree.overflow.get(&v))
+    }
+
+    pub fn contains<Q>(&self, value: &Q) -> bool
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        self.find_node(value).is_some()
+    }
+
+    // (値, 個数)を昇順に返す
+    pub fn iter_counts(&self) -> impl Iterator<Item = (T, usize)> + '_ {
+        self.tree.iter_counts().map(|(v, count)| (v.value, count))
+    }
+
+    pub fn total_weight(&self) -> u64 {
+        subtree_sum(&self.tree.root)
+    }
+
+    // valueより小さい要素の重みの合計
+    pub fn weight_prefix<Q>(&self, value: &Q) -> u64
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        let mut prefix = 0;
+        let mut now = self.tree.root.clone();
+        while let Some(node) = now {
+            let n = node.borrow();
+            now = if value <= value_key(&n.data) {
+                n.left.clone()
+            } else {
+                prefix += subtree_sum(&n.left) + n.data.weight * self.tree.overflow.get(&node) as u64;
+                n.right.clone()
+            };
+        }
+        prefix
+    }
+
+    /*
+        要素を昇順に重みの長さで並べたとき、位置wにある要素を返す
+        weight_prefix(x) <= w < weight_prefix(x) + 重み * 個数 となるx 重みが0の要素は返らない
+    */
+    pub fn find_by_weight(&self, mut w: u64) -> Option<T> {
+        let mut now = self.tree.root.clone();
+        while let Some(node) = now {
+            let n = node.borrow();
+            let left = subtree_sum(&n.left);
+            if w < left {
+                now = n.left.clone();
+                continue;
+            }
+            w -= left;
+            let own = n.data.weight * self.tree.overflow.get(&node) as u64;
+            if w < own {
+                return Some( n.data.value.clone() );
+            }
+            w -= own;
+            now = n.right.clone();
+        }
+        None
+    }
+
+    // 重みに比例した確率で要素を１つ選ぶ 重みの合計が0ならNone
+    #[cfg(feature = "rand")]
+    pub fn sample<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> Option<T> {
+        let total = self.total_weight();
+        if total == 0 {
+            return None;
+        }
+        self.find_by_weight(rng.gen_range(0..total))
+    }
+
+    fn find_node<Q>(&self, value: &Q) -> Option<NodeRef<Weighted<T>>>
+        where T: borrow::Borrow<Q>, Q: Ord + ?Sized
+    {
+        self.tree.find_node_by(value, value_key)
+    }
+}
+
+// テスト用関数 元の木の不変条件と重みの合計を確認する
+#[cfg(test)]
+impl<T: Ord + Clone + core::fmt::Debug + 'static, P: BalancePolicy> WeightedMultiAVL<T, P> {
+    pub fn check_consistent(&self) -> Result<(), alloc::boxed::Box<dyn std::error::Error>> {
+        self.tree.check_consistent()?;
+        if let Some(root) = &self.tree.root {
+            self.dfs_check_sum(root)?;
+        }
+        Ok(())
+    }
+
+    fn dfs_check_sum(&self, node: &NodeRef<Weighted<T>>) -> Result<u64, alloc::boxed::Box<dyn std::error::Error>> {
+        let n = node.borrow();
+        let mut sum = n.data.weight * self.tree.overflow.get(node) as u64;
+        for child in [&n.left, &n.right].into_iter().flatten() {
+            sum += self.dfs_check_sum(child)?;
+        }
+        if n.data.sum != sum {
+            return Err( alloc::format!("weight sum of {:?} is {} but should be {}", n.data.value, n.data.sum, sum).into() );
+        }
+        Ok(sum)
+    }
+}
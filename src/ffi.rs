@@ -0,0 +1,249 @@
+/*
+    C から使うための関数 ヘッダは include/multi_avl.h (ffi_check がこのファイルから生成したものと一致するか確かめる)
+    木やカーソルは不透明なハンドルで渡し、*_new / *_free で作って捨てる
+    ハンドルには NULL や解放済みのものを渡してはいけない 同じ木を複数のスレッドから同時に触ってもいけない
+    カーソルは木より長生きしても安全だが、カーソルを進める間に木を変えたときの結果は決めていない
+*/
+#![allow(clippy::missing_safety_doc)]
+
+use core::ops::Bound;
+use core::ptr;
+use core::slice;
+
+use crate::avl::{MultiAVL, MultiAVLTreeCountIter};
+
+// int64_t の多重集合
+pub struct MultiAVLI64(MultiAVL<i64>);
+
+// バイト列の多重集合 バイト列は辞書順に並べる
+pub struct MultiAVLBytes(MultiAVL<Vec<u8>>);
+
+// 昇順に (値, 個数) を返すカーソル
+pub struct MultiAVLI64Cursor(MultiAVLTreeCountIter<i64>);
+
+pub struct MultiAVLBytesCursor(MultiAVLTreeCountIter<Vec<u8>>);
+
+// Rust 側で確保したバイト列 使い終わったら multi_avl_byte_buf_free で解放する
+#[repr(C)]
+pub struct MultiAVLByteBuf {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl MultiAVLByteBuf {
+    fn from_vec(value: Vec<u8>) -> MultiAVLByteBuf {
+        let len = value.len();
+        let data = Box::into_raw(value.into_boxed_slice()) as *mut u8;
+        MultiAVLByteBuf { data, len }
+    }
+}
+
+// len が 0 なら data は NULL でもよい
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, len)
+    }
+}
+
+// out が NULL でなければ書き込む
+unsafe fn put<T>(out: *mut T, value: T) {
+    if !out.is_null() {
+        ptr::write(out, value);
+    }
+}
+
+// 値があれば out に書き込んで true を返す
+unsafe fn write_out<T>(value: Option<T>, out: *mut T) -> bool {
+    match value {
+        Some(v) => {
+            put(out, v);
+            true
+        },
+        None => false,
+    }
+}
+
+// out が NULL ならコピーを作らない
+unsafe fn write_buf(value: Option<Vec<u8>>, out: *mut MultiAVLByteBuf) -> bool {
+    match value {
+        Some(v) => {
+            if !out.is_null() {
+                put(out, MultiAVLByteBuf::from_vec(v));
+            }
+            true
+        },
+        None => false,
+    }
+}
+
+// 個数が usize::MAX を超える挿入は木を変えずに 0 を返す
+fn insert<T: Ord + Clone>(tree: &mut MultiAVL<T>, value: T) -> usize {
+    tree.try_insert(value).unwrap_or(0)
+}
+
+// == int64_t ==
+
+#[no_mangle]
+pub extern "C" fn multi_avl_i64_new() -> *mut MultiAVLI64 {
+    Box::into_raw(Box::new(MultiAVLI64(MultiAVL::new())))
+}
+
+// NULL なら何もしない
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_i64_free(tree: *mut MultiAVLI64) {
+    if !tree.is_null() {
+        drop(Box::from_raw(tree));
+    }
+}
+
+// 挿入後の value の個数を返す
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_i64_insert(tree: *mut MultiAVLI64, value: i64) -> usize {
+    insert(&mut (*tree).0, value)
+}
+
+// value を１つ取り除き、取り除けたかを返す
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_i64_erase(tree: *mut MultiAVLI64, value: i64) -> bool {
+    (*tree).0.erase(&value)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_i64_count(tree: *const MultiAVLI64, value: i64) -> usize {
+    (*tree).0.count(&value)
+}
+
+// 重複を含めた要素数
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_i64_len(tree: *const MultiAVLI64) -> usize {
+    (*tree).0.size()
+}
+
+// 空なら false を返し out は変えない
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_i64_min(tree: *const MultiAVLI64, out: *mut i64) -> bool {
+    write_out((*tree).0.min_value(), out)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_i64_max(tree: *const MultiAVLI64, out: *mut i64) -> bool {
+    write_out((*tree).0.max_value(), out)
+}
+
+// 最小の値から始まるカーソル
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_i64_iter(tree: *const MultiAVLI64) -> *mut MultiAVLI64Cursor {
+    Box::into_raw(Box::new(MultiAVLI64Cursor((*tree).0.iter_counts())))
+}
+
+// value 以上の最小の値から始まるカーソル
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_i64_lower_bound(tree: *const MultiAVLI64, value: i64) -> *mut MultiAVLI64Cursor {
+    Box::into_raw(Box::new(MultiAVLI64Cursor((*tree).0.range_counts(value..))))
+}
+
+// 次の値とその個数を書き込んで進む 終わりなら false out と count は NULL でもよい
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_i64_cursor_next(cursor: *mut MultiAVLI64Cursor, out: *mut i64, count: *mut usize) -> bool {
+    match (*cursor).0.next() {
+        Some((v, c)) => {
+            put(out, v);
+            put(count, c);
+            true
+        },
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_i64_cursor_free(cursor: *mut MultiAVLI64Cursor) {
+    if !cursor.is_null() {
+        drop(Box::from_raw(cursor));
+    }
+}
+
+// == バイト列 ==
+// 値は (data, len) で渡し、木は中身をコピーして持つ
+
+#[no_mangle]
+pub extern "C" fn multi_avl_bytes_new() -> *mut MultiAVLBytes {
+    Box::into_raw(Box::new(MultiAVLBytes(MultiAVL::new())))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_bytes_free(tree: *mut MultiAVLBytes) {
+    if !tree.is_null() {
+        drop(Box::from_raw(tree));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_bytes_insert(tree: *mut MultiAVLBytes, data: *const u8, len: usize) -> usize {
+    insert(&mut (*tree).0, bytes(data, len).to_vec())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_bytes_erase(tree: *mut MultiAVLBytes, data: *const u8, len: usize) -> bool {
+    (*tree).0.erase(bytes(data, len))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_bytes_count(tree: *const MultiAVLBytes, data: *const u8, len: usize) -> usize {
+    (*tree).0.count(bytes(data, len))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_bytes_len(tree: *const MultiAVLBytes) -> usize {
+    (*tree).0.size()
+}
+
+// 見つかれば値のコピーを out に書き込む out は multi_avl_byte_buf_free で解放する
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_bytes_min(tree: *const MultiAVLBytes, out: *mut MultiAVLByteBuf) -> bool {
+    write_buf((*tree).0.min_value(), out)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_bytes_max(tree: *const MultiAVLBytes, out: *mut MultiAVLByteBuf) -> bool {
+    write_buf((*tree).0.max_value(), out)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_bytes_iter(tree: *const MultiAVLBytes) -> *mut MultiAVLBytesCursor {
+    Box::into_raw(Box::new(MultiAVLBytesCursor((*tree).0.iter_counts())))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_bytes_lower_bound(tree: *const MultiAVLBytes, data: *const u8, len: usize) -> *mut MultiAVLBytesCursor {
+    let range = (Bound::Included(bytes(data, len)), Bound::Unbounded);
+    Box::into_raw(Box::new(MultiAVLBytesCursor((*tree).0.range_counts::<[u8], _>(range))))
+}
+
+// out には値のコピーを書き込む
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_bytes_cursor_next(cursor: *mut MultiAVLBytesCursor, out: *mut MultiAVLByteBuf, count: *mut usize) -> bool {
+    match (*cursor).0.next() {
+        Some((v, c)) => {
+            put(count, c);
+            write_buf(Some(v), out)
+        },
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_bytes_cursor_free(cursor: *mut MultiAVLBytesCursor) {
+    if !cursor.is_null() {
+        drop(Box::from_raw(cursor));
+    }
+}
+
+// data が NULL なら何もしない
+#[no_mangle]
+pub unsafe extern "C" fn multi_avl_byte_buf_free(buf: MultiAVLByteBuf) {
+    if !buf.data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buf.data, buf.len)));
+    }
+}
@@ -1,4 +1,12 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
 pub mod avl;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 
 #[cfg(test)]
 mod test;
\ No newline at end of file
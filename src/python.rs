@@ -0,0 +1,229 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::panic::{self, AssertUnwindSafe};
+
+use pyo3::exceptions::{PyIndexError, PyKeyError, PyRuntimeError};
+use pyo3::prelude::*;
+use pyo3::types::PyIterator;
+
+use crate::avl::{MultiAVL, MultiAVLTreeIter};
+
+/*
+    Pythonのオブジェクトを Python の比較演算(<, ==)の順で並べる
+    比較が例外を投げたときはOrdを返せないので、例外を退避してunwindし、
+    メソッドの入口(guarded)で受け止めて例外に戻す
+    木を書き換えるのは比較をすべて終えた後なので、例外が出ても木は変わらない
+*/
+struct PyKey(Py<PyAny>);
+
+thread_local! {
+    static COMPARE_ERROR: RefCell<Option<PyErr>> = const { RefCell::new(None) };
+}
+
+// 比較の失敗で unwind していることを表す
+struct CompareFailed;
+
+impl Clone for PyKey {
+    fn clone(&self) -> PyKey {
+        Python::with_gil(|py| PyKey(self.0.clone_ref(py)))
+    }
+}
+
+impl Ord for PyKey {
+    fn cmp(&self, other: &PyKey) -> Ordering {
+        Python::with_gil(|py| match self.0.bind(py).compare(other.0.bind(py)) {
+            Ok(v) => v,
+            Err(e) => {
+                COMPARE_ERROR.with(|slot| *slot.borrow_mut() = Some(e));
+                // パニックフックを通さずに unwind する
+                panic::resume_unwind(Box::new(CompareFailed))
+            },
+        })
+    }
+}
+
+impl PartialOrd for PyKey {
+    fn partial_cmp(&self, other: &PyKey) -> Option<Ordering> {
+        Some( self.cmp(other) )
+    }
+}
+
+impl PartialEq for PyKey {
+    fn eq(&self, other: &PyKey) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for PyKey {}
+
+// 比較で出た例外をPyErrにして返す それ以外のパニックはそのまま伝える
+fn guarded<R>(f: impl FnOnce() -> R) -> PyResult<R> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        if !payload.is::<CompareFailed>() {
+            panic::resume_unwind(payload);
+        }
+        COMPARE_ERROR.with(|slot| slot.borrow_mut().take())
+            .expect("guarded: comparison error was not stored")
+    })
+}
+
+fn key(value: &Bound<'_, PyAny>) -> PyKey {
+    PyKey(value.clone().unbind())
+}
+
+fn to_object(py: Python<'_>, value: Option<PyKey>) -> Option<PyObject> {
+    value.map(|v| v.0.clone_ref(py))
+}
+
+/*
+    Pythonから使う多重集合
+    要素は互いに < と == で比較できれば何でもよい 比較できない組み合わせを入れようとするとTypeError
+*/
+#[pyclass(unsendable, name = "MultiAVL", module = "multi_avl")]
+pub struct PyMultiAVL {
+    tree: MultiAVL<PyKey>,
+    // 反復中の変更を検出するための変更回数
+    version: u64,
+}
+
+#[pymethods]
+impl PyMultiAVL {
+    #[new]
+    #[pyo3(signature = (iterable = None))]
+    fn new(iterable: Option<&Bound<'_, PyAny>>) -> PyResult<PyMultiAVL> {
+        let mut set = PyMultiAVL { tree: MultiAVL::new(), version: 0 };
+        if let Some(iterable) = iterable {
+            for value in PyIterator::from_object(iterable)? {
+                set.add(&value?)?;
+            }
+        }
+        Ok(set)
+    }
+
+    // 追加後の個数を返す
+    fn add(&mut self, value: &Bound<'_, PyAny>) -> PyResult<usize> {
+        let count = guarded(|| self.tree.insert(key(value)))?;
+        self.version += 1;
+        Ok(count)
+    }
+
+    // １つ取り除く なければKeyError
+    fn remove(&mut self, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        if self.discard(value)? {
+            Ok(())
+        } else {
+            Err( PyKeyError::new_err(value.clone().unbind()) )
+        }
+    }
+
+    // １つ取り除き、取り除けたかを返す
+    fn discard(&mut self, value: &Bound<'_, PyAny>) -> PyResult<bool> {
+        let erased = guarded(|| self.tree.erase(&key(value)))?;
+        if erased {
+            self.version += 1;
+        }
+        Ok(erased)
+    }
+
+    fn count(&self, value: &Bound<'_, PyAny>) -> PyResult<usize> {
+        guarded(|| self.tree.count(&key(value)))
+    }
+
+    fn __contains__(&self, value: &Bound<'_, PyAny>) -> PyResult<bool> {
+        guarded(|| self.tree.contains(&key(value)))
+    }
+
+    fn __len__(&self) -> usize {
+        self.tree.size()
+    }
+
+    fn distinct_len(&self) -> usize {
+        self.tree.distinct_len()
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> MultiAVLIter {
+        MultiAVLIter { iter: slf.tree.iter(), version: slf.version, owner: slf.into() }
+    }
+
+    // 昇順で i 番目の要素 負の添字は末尾から数える
+    fn kth(&self, py: Python<'_>, i: isize) -> PyResult<PyObject> {
+        let len = self.tree.size() as isize;
+        let index = if i < 0 { i + len } else { i };
+        if !(0..len).contains(&index) {
+            return Err( PyIndexError::new_err("MultiAVL index out of range") );
+        }
+        Ok( to_object(py, self.tree.nth(index as usize)).unwrap() )
+    }
+
+    // valueより小さい要素の数
+    fn rank(&self, value: &Bound<'_, PyAny>) -> PyResult<usize> {
+        guarded(|| self.tree.rank(&key(value)))
+    }
+
+    fn min(&self, py: Python<'_>) -> Option<PyObject> {
+        to_object(py, self.tree.min_value())
+    }
+
+    fn max(&self, py: Python<'_>) -> Option<PyObject> {
+        to_object(py, self.tree.max_value())
+    }
+
+    // value以上の最小の要素
+    fn lower_bound(&self, value: &Bound<'_, PyAny>) -> PyResult<Option<PyObject>> {
+        let found = guarded(|| self.tree.ceil(&key(value)))?;
+        Ok( to_object(value.py(), found) )
+    }
+
+    // valueより大きい最小の要素
+    fn upper_bound(&self, value: &Bound<'_, PyAny>) -> PyResult<Option<PyObject>> {
+        let found = guarded(|| self.tree.successor(&key(value)))?;
+        Ok( to_object(value.py(), found) )
+    }
+
+    // value以下の最大の要素
+    fn floor(&self, value: &Bound<'_, PyAny>) -> PyResult<Option<PyObject>> {
+        let found = guarded(|| self.tree.floor(&key(value)))?;
+        Ok( to_object(value.py(), found) )
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        let items = self.tree.iter()
+            .map(|v| Ok( v.0.bind(py).repr()?.to_string() ))
+            .collect::<PyResult<Vec<_>>>()?;
+        Ok( format!("MultiAVL([{}])", items.join(", ")) )
+    }
+}
+
+// 昇順の反復子 反復中に元の集合が変わるとRuntimeError
+#[pyclass(unsendable)]
+pub struct MultiAVLIter {
+    owner: Py<PyMultiAVL>,
+    iter: MultiAVLTreeIter<PyKey>,
+    version: u64,
+}
+
+#[pymethods]
+impl MultiAVLIter {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        if self.owner.borrow(py).version != self.version {
+            return Err( PyRuntimeError::new_err("MultiAVL changed during iteration") );
+        }
+        Ok( to_object(py, self.iter.next()) )
+    }
+}
+
+// Pythonの multi_avl モジュールに登録する
+pub fn register(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyMultiAVL>()?;
+    module.add_class::<MultiAVLIter>()?;
+    Ok(())
+}
+
+#[pymodule]
+fn multi_avl(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    register(module)
+}
@@ -1,5 +1,10 @@
-use crate::avl::MultiAVL;
-use rand::{ SeedableRng, seq::SliceRandom, rngs::StdRng };
+// 元からあるテストの書き方はそのまま残す
+#![allow(clippy::bool_assert_comparison, clippy::ptr_arg, clippy::legacy_numeric_constants)]
+
+use crate::avl::{MultiAVL, IterError, CountOverflow, IntervalMultiAVL, WeightedMultiAVL, BoundedMultiAVL, Keep, Entry, ValidationError, BalancePolicy, Avl, RedBlack};
+use std::collections::BTreeMap;
+use rand::{ Rng, SeedableRng, seq::SliceRandom, rngs::StdRng };
+use std::ops::Bound;
 
 fn setup_tree(values: &Vec<i32>) -> MultiAVL<i32> {
     let mut tree = MultiAVL::new();
@@ -13,8 +18,24 @@ fn setup_tree(values: &Vec<i32>) -> MultiAVL<i32> {
 fn test_contains() {
     let mut tree = MultiAVL::new();
     tree.insert(1);
-    assert!(tree.contains(1));
-    assert!(!tree.contains(0));
+    assert!(tree.contains(&1));
+    assert!(!tree.contains(&0));
+}
+
+#[test]
+fn test_contains_borrowed() {
+    let mut tree = MultiAVL::new();
+    tree.insert(String::from("apple"));
+    tree.insert(String::from("banana"));
+    tree.insert(String::from("banana"));
+    assert!(tree.contains("apple"));
+    assert!(!tree.contains("cherry"));
+
+    tree.erase("banana");
+    assert!(tree.contains("banana"));
+    tree.erase("banana");
+    assert!(!tree.contains("banana"));
+    assert_eq!(tree.size(), 1);
 }
 
 #[test]
@@ -23,7 +44,7 @@ fn test_size() {
     assert_eq!(tree.size(), 0);
     tree.insert(1);
     assert_eq!(tree.size(), 1);
-    tree.erase(1);
+    tree.erase(&1);
     assert_eq!(tree.size(), 0);
 }
 
@@ -33,11 +54,11 @@ fn test_insert_ascending_order() {
     let n = 1_000;
     let mut tree = MultiAVL::new();
     for i in 0..n {
-        assert_eq!(tree.contains(i), false);
+        assert_eq!(tree.contains(&i), false);
         tree.insert(i);
     }
     for i in 0..n {
-        assert_eq!(tree.contains(i), true);
+        assert_eq!(tree.contains(&i), true);
     }
 
     assert!(tree.check_consistent().is_ok());
@@ -49,11 +70,11 @@ fn test_insert_descending_order() {
     let n = 1_000;
     let mut tree = MultiAVL::new();
     for i in (0..n).rev() {
-        assert_eq!(tree.contains(i), false);
+        assert_eq!(tree.contains(&i), false);
         tree.insert(i);
     }
     for i in 0..n {
-        assert_eq!(tree.contains(i), true);
+        assert_eq!(tree.contains(&i), true);
     }
 
     assert!(tree.check_consistent().is_ok());
@@ -76,7 +97,7 @@ fn test_insert_shuffled() {
     assert_eq!(tree.size(), n as usize);
 
     for i in &nums {
-        assert!(tree.contains(*i));
+        assert!(tree.contains(i));
     }
 }
 
@@ -85,14 +106,14 @@ fn do_erase_test(test_cases: &Vec<(Vec<i32>, i32)>) {
     for (case_num, (values, target)) in test_cases.iter().enumerate() {
         let mut tree = setup_tree(values);
         for i in values {
-            tree.contains(*i);
+            tree.contains(i);
         }
 
-        tree.erase(*target);
-        assert!(!tree.contains(*target), "case {} failed", case_num);
+        tree.erase(target);
+        assert!(!tree.contains(target), "case {} failed", case_num);
         for i in values {
             if *i == *target { continue; }
-            assert!(tree.contains(*i), "case {} failed", case_num);
+            assert!(tree.contains(i), "case {} failed", case_num);
         }
 
         assert_eq!(tree.size(), values.len() - 1, "case {} failed", case_num);
@@ -253,7 +274,7 @@ fn test_erase_not_exist() {
     tree.insert(3);
 
     assert_eq!(tree.size(), 3);
-    tree.erase(10);
+    tree.erase(&10);
     assert_eq!(tree.size(), 3);
 }
 
@@ -273,10 +294,10 @@ fn test_erase_shuffled() {
     assert_eq!(tree.size(), expected_size);
     nums.shuffle(&mut rng);
     for i in &nums {
-        assert_eq!(tree.contains(*i), true);
-        tree.erase(*i);
+        assert_eq!(tree.contains(i), true);
+        tree.erase(i);
         expected_size -= 1;
-        assert_eq!(tree.contains(*i), false);
+        assert_eq!(tree.contains(i), false);
         assert_eq!(tree.size(), expected_size);
 
         assert!(tree.check_consistent().is_ok());
@@ -289,7 +310,7 @@ fn do_rotate_test(test_cases: &Vec<Vec<i32>>) {
     for (case_num, values) in test_cases.iter().enumerate() {
         let tree = setup_tree(values);
         for i in values {
-            tree.contains(*i);
+            tree.contains(i);
         }
         assert!(tree.check_consistent().is_ok(), "case {} failed", case_num);
     }
@@ -500,7 +521,7 @@ fn test_max_value() {
     nums.shuffle(&mut rng);
     loop {
         let v = nums.pop().unwrap();
-        tree.erase(v);
+        tree.erase(&v);
         if nums.is_empty() { break; }
         let mx = nums.iter().max().unwrap();
         assert_eq!(tree.max_value().unwrap(), *mx);
@@ -527,7 +548,7 @@ fn test_min_value() {
     nums.shuffle(&mut rng);
     loop {
         let v = nums.pop().unwrap();
-        tree.erase(v);
+        tree.erase(&v);
         if nums.is_empty() { break; }
         let mx = nums.iter().min().unwrap();
         assert_eq!(tree.min_value().unwrap(), *mx);
@@ -550,7 +571,7 @@ fn test_multi_insert() {
     let mut rng = StdRng::seed_from_u64(0);
     nums.shuffle(&mut rng);
     for i in &nums {
-        tree.insert(*i as i32)
+        tree.insert(*i as i32);
     }
 
     assert_eq!(tree.size(), 3*n);
@@ -574,7 +595,1852 @@ fn test_allow_multi() {
     tree.insert(2);
     
     assert_eq!(tree.size(), 4);
-    assert_eq!(tree.contains(1), true);
-    assert_eq!(tree.contains(2), true);
+    assert_eq!(tree.contains(&1), true);
+    assert_eq!(tree.contains(&2), true);
+    assert!(tree.check_consistent().is_ok());
+}
+
+// == checkpoint / rollback テスト ==
+#[test]
+fn test_rollback() {
+    let n = 1_000;
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut nums: Vec<i32> = (0..n).map(|i| i % 100).collect();
+    nums.shuffle(&mut rng);
+    let mut tree = setup_tree(&nums);
+    let before: Vec<i32> = tree.iter().collect();
+
+    let checkpoint = tree.checkpoint();
+    for i in &nums[..n as usize / 2] {
+        tree.erase(i);
+        tree.insert(*i + 1000);
+        tree.insert(-*i - 1);
+    }
+    tree.erase(&12345);
+    assert!(tree.check_consistent().is_ok());
+
+    tree.rollback(checkpoint);
+    assert!(tree.check_consistent().is_ok());
+    assert_eq!(tree.size(), n as usize);
+    assert_eq!(tree.min_value(), Some(0));
+    assert_eq!(tree.max_value(), Some(99));
+    assert_eq!(tree.iter().collect::<Vec<_>>(), before);
+}
+
+#[test]
+fn test_rollback_nested() {
+    let mut tree = setup_tree(&vec![1, 2, 3]);
+    let outer = tree.checkpoint();
+    tree.insert(4);
+    let inner = tree.checkpoint();
+    tree.erase(&1);
+    tree.erase(&2);
+    tree.insert(0);
+
+    tree.rollback(inner);
+    assert!(tree.check_consistent().is_ok());
+    assert_eq!(tree.iter().collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+
+    // 巻き戻した後もcheckpointは有効
+    tree.erase(&3);
+    tree.rollback(inner);
+    assert_eq!(tree.iter().collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+
+    tree.rollback(outer);
+    assert!(tree.check_consistent().is_ok());
+    assert_eq!(tree.iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert_eq!(tree.max_value(), Some(3));
+
+    // 外側のcheckpointまで戻すと内側のcheckpointは無効になる
+    tree.insert(5);
+    tree.insert(6);
+    tree.rollback(inner);
+    assert_eq!(tree.iter().collect::<Vec<_>>(), vec![1, 2, 3, 5, 6]);
+}
+
+#[test]
+fn test_checkpoint_other_tree() {
+    let mut a = setup_tree(&vec![1, 2]);
+    let mut b = setup_tree(&vec![1, 2]);
+    let checkpoint_a = a.checkpoint();
+    let checkpoint_b = b.checkpoint();
+    a.insert(3);
+    b.insert(3);
+
+    // 他の木のcheckpointでは巻き戻しも確定もされない
+    b.rollback(checkpoint_a);
+    b.commit(checkpoint_a);
+    assert_eq!(b.iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+    a.commit(checkpoint_b);
+    a.rollback(checkpoint_a);
+    assert_eq!(a.iter().collect::<Vec<_>>(), vec![1, 2]);
+
+    // bのcheckpointは有効なまま
+    b.rollback(checkpoint_b);
+    assert_eq!(b.iter().collect::<Vec<_>>(), vec![1, 2]);
+}
+
+#[test]
+fn test_commit() {
+    let mut tree = setup_tree(&vec![1, 2, 3]);
+    let checkpoint = tree.checkpoint();
+    assert_eq!(tree.erase_iter(tree.min_iter().unwrap()), Ok(1));
+    let inner = tree.checkpoint();
+    tree.insert(4);
+    tree.commit(inner);
+    tree.rollback(inner);
+    assert_eq!(tree.iter().collect::<Vec<_>>(), vec![2, 3, 4]);
+    tree.commit(checkpoint);
+
+    tree.rollback(checkpoint);
+    assert_eq!(tree.iter().collect::<Vec<_>>(), vec![2, 3, 4]);
+
+    let checkpoint = tree.checkpoint();
+    assert_eq!(tree.erase_iter(tree.max_iter().unwrap()), Ok(4));
+    tree.rollback(checkpoint);
+    assert_eq!(tree.iter().collect::<Vec<_>>(), vec![2, 3, 4]);
+    assert!(tree.check_consistent().is_ok());
+}
+
+// == 操作の結果テスト ==
+#[test]
+fn test_mutation_results() {
+    let mut tree = MultiAVL::new();
+    assert_eq!(tree.insert(1), 1);
+    assert_eq!(tree.insert(1), 2);
+    assert_eq!(tree.insert(2), 1);
+    assert_eq!(tree.try_insert(1), Ok(3));
+
+    assert!(tree.erase(&1));
+    assert!(!tree.erase(&5));
+    assert_eq!(tree.count(&1), 2);
+
+    // 最後の要素より後ろ
+    let mut iter = tree.max_iter().unwrap();
+    iter.next();
+    assert_eq!(tree.erase_iter(iter), Err(IterError::Exhausted));
+
+    // 消された要素を指している
+    let iter = tree.max_iter().unwrap();
+    assert_eq!(tree.erase_iter(tree.max_iter().unwrap()), Ok(2));
+    assert_eq!(tree.erase_iter(iter), Err(IterError::Dangling));
+    assert_eq!(tree.iter().collect::<Vec<_>>(), vec![1, 1]);
+    assert!(tree.check_consistent().is_ok());
+}
+
+fn check_erase_iter_after_two_children_erase<P: BalancePolicy>() {
+    let mut tree = MultiAVL::<i32, P>::with_policy();
+    for v in [2, 1, 3] {
+        tree.insert(v);
+    }
+    // itは次に2を返す位置にある
+    let mut it = tree.iter();
+    assert_eq!(it.next(), Some(1));
+    let min_it = tree.min_iter().unwrap();
+
+    // 消した2を指すイテレータはDanglingになり、左部分木の最大値の1を指すイテレータはそのまま使える
+    assert!(tree.erase(&2));
+    assert_eq!(tree.erase_iter(it), Err(IterError::Dangling));
+    assert_eq!(tree.iter().collect::<Vec<_>>(), vec![1, 3]);
+    assert_eq!(tree.erase_iter(min_it), Ok(1));
+    assert_eq!(tree.iter().collect::<Vec<_>>(), vec![3]);
+    assert!(tree.check_consistent().is_ok());
+}
+
+#[test]
+fn test_erase_iter_after_two_children_erase() {
+    check_erase_iter_after_two_children_erase::<Avl>();
+    check_erase_iter_after_two_children_erase::<RedBlack>();
+}
+
+#[test]
+fn test_try_insert_overflow() {
+    let mut tree = MultiAVL::new();
+    tree.insert_n(1, usize::MAX - 1);
+    assert_eq!(tree.try_insert(2), Ok(1));
+    assert_eq!(tree.try_insert(1), Err(CountOverflow { value: 1 }));
+    assert_eq!(tree.size(), usize::MAX);
+    assert_eq!(tree.count(&1), usize::MAX - 1);
+    assert!(tree.check_consistent().is_ok());
+}
+
+#[test]
+#[should_panic(expected = "insert: element count overflow")]
+fn test_insert_overflow() {
+    let mut tree = MultiAVL::new();
+    tree.insert_n(1, usize::MAX);
+
+    // try_insertは木を変えずに失敗する
+    assert_eq!(tree.try_insert(1), Err(CountOverflow { value: 1 }));
+    assert_eq!(tree.try_insert(2), Err(CountOverflow { value: 2 }));
+    assert_eq!(tree.size(), usize::MAX);
+    assert_eq!(tree.count(&1), usize::MAX);
+    assert!(!tree.contains(&2));
+
+    tree.insert(1);
+}
+
+#[test]
+#[should_panic(expected = "insert: element count overflow")]
+fn test_insert_n_overflow() {
+    let mut tree = MultiAVL::new();
+    tree.insert_n(1, usize::MAX);
+    tree.insert_n(1, 1);
+}
+
+#[test]
+fn test_try_insert_n_overflow() {
+    let mut tree = MultiAVL::new();
+    assert_eq!(tree.try_insert_n(1, usize::MAX - 1), Ok(usize::MAX - 1));
+    assert_eq!(tree.try_insert_n(1, 2).map_err(|e| e.value), Err(1));
+    assert_eq!(tree.try_insert_n(2, 2).map_err(|e| e.value), Err(2));
+    assert_eq!(tree.size(), usize::MAX - 1);
+    assert_eq!(tree.count(&1), usize::MAX - 1);
+    assert!(!tree.contains(&2));
+    assert!(tree.check_consistent().is_ok());
+
+    assert_eq!(tree.try_insert_n(2, 1), Ok(1));
+    assert_eq!(tree.try_insert_n(2, 0).map_err(|e| e.value), Ok(1));
+    assert_eq!(tree.size(), usize::MAX);
+}
+
+// == entry テスト ==
+#[test]
+fn test_entry_insert() {
+    let mut tree = setup_tree(&vec![1, 3]);
+    match tree.entry(2) {
+        Entry::Occupied(_) => panic!("2 is not in the tree"),
+        Entry::Vacant(v) => {
+            assert_eq!(*v.key(), 2);
+            v.insert_n(3);
+        },
+    }
+    assert_eq!(tree.entry(2).count(), 3);
+
+    match tree.entry(2) {
+        Entry::Occupied(mut v) => {
+            v.insert_n(3);
+            assert_eq!(v.get(), 2);
+            assert_eq!(v.count(), 6);
+        },
+        Entry::Vacant(_) => panic!("2 is in the tree"),
+    }
+    tree.entry(0).insert_n(2);
+    tree.entry(4).insert_n(1);
+
+    assert_eq!(tree.size(), 11);
+    assert_eq!(tree.min_value(), Some(0));
+    assert_eq!(tree.max_value(), Some(4));
+    assert_eq!(tree.iter().collect::<Vec<_>>(), vec![0, 0, 1, 2, 2, 2, 2, 2, 2, 3, 4]);
+    assert!(tree.check_consistent().is_ok());
+}
+
+#[test]
+fn test_entry_remove() {
+    let mut tree = setup_tree(&vec![1, 2, 2, 2, 3]);
+    if let Entry::Occupied(v) = tree.entry(2) {
+        assert_eq!(v.remove_n(2), 2);
+    }
+    assert_eq!(tree.entry(2).count(), 1);
+    if let Entry::Occupied(v) = tree.entry(2) {
+        assert_eq!(v.remove_n(5), 1);
+    }
+    assert!(!tree.contains(&2));
+
+    if let Entry::Occupied(v) = tree.entry(3) {
+        v.set_count(4);
+    }
+    assert_eq!(tree.size(), 5);
+    if let Entry::Occupied(v) = tree.entry(3) {
+        assert_eq!(v.remove_entry(), (3, 4));
+    }
+    assert_eq!(tree.max_value(), Some(1));
+
+    if let Entry::Occupied(v) = tree.entry(1) {
+        v.set_count(0);
+    }
+    assert!(tree.is_empty());
+    assert_eq!(tree.min_value(), None);
+    assert!(tree.check_consistent().is_ok());
+}
+
+#[test]
+fn test_entry_shuffled() {
+    let n = 1_000;
+    let mut nums: Vec<i32> = (0..n).collect();
+    let mut rng = StdRng::seed_from_u64(0);
+    nums.shuffle(&mut rng);
+
+    let mut tree = MultiAVL::new();
+    for i in &nums {
+        tree.entry(*i).insert_n((*i % 3 + 1) as usize);
+    }
+    assert!(tree.check_consistent().is_ok());
+
+    let checkpoint = tree.checkpoint();
+    nums.shuffle(&mut rng);
+    for i in &nums {
+        if let Entry::Occupied(v) = tree.entry(*i) {
+            v.remove_n(2);
+        }
+        assert!(tree.check_consistent().is_ok());
+    }
+    assert_eq!(tree.size(), (0..n).filter(|i| i % 3 == 2).count());
+
+    tree.rollback(checkpoint);
+    assert!(tree.check_consistent().is_ok());
+    assert_eq!(tree.size(), (0..n).map(|i| (i % 3 + 1) as usize).sum::<usize>());
+}
+
+// == insert_n / erase_n テスト ==
+#[test]
+fn test_insert_n() {
+    let mut tree = MultiAVL::new();
+    assert_eq!(tree.insert_n(1, 1_000_000_000), 1_000_000_000);
+    assert_eq!(tree.insert_n(2, 3), 3);
+    assert_eq!(tree.insert_n(0, 0), 0);
+    assert_eq!(tree.insert(2), 4);
+    assert_eq!(tree.insert_n(2, 2), 6);
+    assert_eq!(tree.insert_n(2, 0), 6);
+    tree.erase_n(&2, 2);
+
+    assert_eq!(tree.size(), 1_000_000_004);
+    assert_eq!(tree.count(&1), 1_000_000_000);
+    assert_eq!(tree.count(&2), 4);
+    assert!(!tree.contains(&0));
+    assert_eq!(tree.min_value(), Some(1));
+    assert!(tree.check_consistent().is_ok());
+}
+
+#[test]
+fn test_erase_n() {
+    let mut tree = MultiAVL::new();
+    for i in 0..100 {
+        tree.insert_n(i, 1_000_000);
+    }
+
+    assert_eq!(tree.erase_n(&10, 999_999), 999_999);
+    assert_eq!(tree.count(&10), 1);
+    assert_eq!(tree.erase_n(&10, 5), 1);
+    assert!(!tree.contains(&10));
+    assert_eq!(tree.erase_n(&10, 5), 0);
+    assert_eq!(tree.erase_n(&0, usize::MAX), 1_000_000);
+    assert_eq!(tree.min_value(), Some(1));
+
+    assert_eq!(tree.size(), 98_000_000);
+    assert!(tree.check_consistent().is_ok());
+}
+
+#[test]
+fn test_iter_counts() {
+    let mut tree = MultiAVL::new();
+    assert_eq!(tree.iter_counts().next(), None);
+
+    let n = 1_000;
+    let mut nums: Vec<i32> = (0..n).collect();
+    let mut rng = StdRng::seed_from_u64(0);
+    nums.shuffle(&mut rng);
+    for i in &nums {
+        tree.insert_n(*i, 1_000_000 + *i as usize);
+    }
+
+    let counts: Vec<(i32, usize)> = tree.iter_counts().collect();
+    assert_eq!(counts, (0..n).map(|i| (i, 1_000_000 + i as usize)).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_for_each_count() {
+    // 比較はできるが複製するとpanicする値
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+    struct NoClone(i32);
+    impl Clone for NoClone {
+        fn clone(&self) -> NoClone {
+            panic!("cloned {:?}", self);
+        }
+    }
+
+    let mut tree = MultiAVL::new();
+    for i in [3, 1, 2, 3, 3] {
+        tree.insert(NoClone(i));
+    }
+    let mut counts = Vec::new();
+    tree.for_each_count(|value, count| counts.push((value.0, count)));
+    assert_eq!(counts, vec![(1, 1), (2, 1), (3, 3)]);
+
+    let mut seen = Vec::new();
+    let result = tree.try_for_each_count(|value, _| {
+        seen.push(value.0);
+        if value.0 == 2 { Err(value.0) } else { Ok(()) }
+    });
+    assert_eq!(result, Err(2));
+    assert_eq!(seen, vec![1, 2]);
+}
+
+#[test]
+fn test_large_count() {
+    // 30ビットに収まらない個数
+    let large = 1 << 31;
+    let mut tree = setup_tree(&(0..10).collect());
+    tree.insert_n(2, large);
+    tree.insert_n(6, large);
+    assert_eq!(tree.count(&2), large + 1);
+    assert_eq!(tree.iter_counts().nth(6), Some((6, large + 1)));
+    assert_eq!(tree.iter().take(4).collect::<Vec<_>>(), vec![0, 1, 2, 2]);
+    assert!(tree.check_consistent().is_ok());
+
+    // 3と7は２つの子を持ち、左部分木の最大の2と6と位置が入れ替わる 個数はノードに付いたまま動かない
+    tree.erase(&3);
+    tree.erase(&7);
+    assert_eq!(tree.count(&2), large + 1);
+    assert_eq!(tree.count(&6), large + 1);
+    assert!(tree.check_consistent().is_ok());
+
+    assert_eq!(tree.erase_n(&2, large), large);
+    assert_eq!(tree.count(&2), 1);
+    assert_eq!(tree.erase_n(&6, usize::MAX), large + 1);
+    assert!(!tree.contains(&6));
+    assert_eq!(tree.size(), 7);
+    assert!(tree.check_consistent().is_ok());
+}
+
+#[test]
+fn test_large_count_reused_address() {
+    let large = 1 << 31;
+    let mut tree = MultiAVL::new();
+    tree.insert_n(1, large);
+    tree.insert_n(2, large + 1);
+    assert_eq!(tree.overflow_len(), 2);
+
+    // イテレータが外れたノードを指したまま消して入れ直す
+    let mut iter = tree.iter();
+    let mut counts = tree.iter_counts();
+    assert_eq!(iter.next(), Some(1));
+    tree.erase_n(&1, usize::MAX);
+    tree.erase_n(&2, usize::MAX);
+    assert_eq!(tree.overflow_len(), 0);
+    for v in 3..10 {
+        tree.insert_n(v, large + v);
+    }
+    assert_eq!(iter.next(), None);
+    assert_eq!(counts.next(), None);
+    drop(iter);
+    drop(counts);
+
+    // 解放されたノードの場所が再利用されても、古い個数は残っていない
+    let mut expected = BTreeMap::new();
+    for round in 0..20 {
+        for v in 0..12 {
+            tree.erase_n(&v, usize::MAX);
+        }
+        expected.clear();
+        for v in 0..12 {
+            let count = if (v + round) % 2 == 0 { large + v } else { 5 };
+            tree.insert_n(v, count);
+            expected.insert(v, count);
+        }
+        assert_eq!(tree.iter_counts().collect::<BTreeMap<_, _>>(), expected);
+        assert_eq!(tree.overflow_len(), 6);
+    }
     assert!(tree.check_consistent().is_ok());
 }
+
+// == 前後の値の探索テスト ==
+#[test]
+fn test_neighbor_queries() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut nums: Vec<i32> = (0..500).map(|i| i * 3).collect();
+    nums.extend((0..100).map(|i| i * 6));
+    nums.shuffle(&mut rng);
+    let tree = setup_tree(&nums);
+
+    for x in -5..1_505 {
+        assert_eq!(tree.predecessor(&x), nums.iter().copied().filter(|v| *v < x).max(), "x = {}", x);
+        assert_eq!(tree.successor(&x), nums.iter().copied().filter(|v| *v > x).min(), "x = {}", x);
+        assert_eq!(tree.floor(&x), nums.iter().copied().filter(|v| *v <= x).max(), "x = {}", x);
+        assert_eq!(tree.ceil(&x), nums.iter().copied().filter(|v| *v >= x).min(), "x = {}", x);
+    }
+}
+
+#[test]
+fn test_nearest() {
+    let tree = setup_tree(&vec![10, 20, 20, 40]);
+    assert_eq!(tree.nearest(&-100), Some(10));
+    assert_eq!(tree.nearest(&14), Some(10));
+    assert_eq!(tree.nearest(&15), Some(10));
+    assert_eq!(tree.nearest(&16), Some(20));
+    assert_eq!(tree.nearest(&20), Some(20));
+    assert_eq!(tree.nearest(&31), Some(40));
+    assert_eq!(tree.nearest(&i32::MAX), Some(40));
+
+    let empty: MultiAVL<u64> = MultiAVL::new();
+    assert_eq!(empty.nearest(&1), None);
+    assert_eq!(empty.floor(&1), None);
+}
+
+// == 検証テスト ==
+#[test]
+fn test_validate() {
+    let mut tree = MultiAVL::new();
+    for i in 0..100 {
+        tree.insert(format!("{:03}", i));
+    }
+    assert_eq!(tree.validate(), Ok(()));
+
+    let mut tree = setup_tree(&(0..100).collect());
+    tree.corrupt_size(1);
+    assert_eq!(tree.validate(), Err(ValidationError::Size { stored: 1, actual: 100 }));
+
+    let mut tree = setup_tree(&(0..100).collect());
+    tree.corrupt_min_node();
+    assert_eq!(tree.validate(), Err(ValidationError::MinNode { cached: Some(99), actual: Some(0) }));
+
+    let mut tree = setup_tree(&(0..100).collect());
+    tree.corrupt_root_balance_bits(1);
+    assert!(matches!(tree.validate(), Err(ValidationError::BalanceFactor { stored: 1, actual: 0, .. })));
+
+    let mut tree = setup_tree(&(0..100).collect());
+    tree.corrupt_order();
+    let err = tree.validate().unwrap_err();
+    assert!(matches!(err, ValidationError::Order { .. }));
+    assert!(err.to_string().starts_with("order is not correct"));
+}
+
+#[cfg(feature = "paranoid")]
+#[test]
+#[should_panic(expected = "invariant violated after insert: min node")]
+fn test_paranoid() {
+    let mut tree = setup_tree(&(0..100).collect());
+    tree.corrupt_min_node();
+    tree.insert(200);
+}
+
+#[cfg(feature = "paranoid")]
+#[test]
+#[should_panic(expected = "invariant violated after rotate: order")]
+fn test_paranoid_rotation() {
+    let mut tree = setup_tree(&vec![0, 2]);
+    // 2が根の左の子になるがバランス係数は-1のままなので、右に1を入れると回転する
+    tree.corrupt_order();
+    tree.insert(1);
+}
+
+// 赤黒木でも回転のたびに確認する
+#[cfg(feature = "paranoid")]
+#[test]
+fn test_paranoid_red_black() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut tree = MultiAVL::<i32, RedBlack>::with_policy();
+    for _ in 0..2_000 {
+        let value = rng.gen_range(0..200);
+        if rng.gen_bool(0.6) {
+            tree.insert(value);
+        } else {
+            tree.erase(&value);
+        }
+    }
+    for v in 0..200 {
+        tree.erase_n(&v, usize::MAX);
+    }
+    assert!(tree.is_empty());
+}
+
+#[cfg(feature = "paranoid")]
+#[test]
+#[should_panic(expected = "invariant violated after rotate: order")]
+fn test_paranoid_red_black_rotation() {
+    let mut tree = MultiAVL::<i32, RedBlack>::with_policy();
+    tree.insert(0);
+    tree.insert(2);
+    // 2が根の左の子になり、-1を入れると赤が続くので回転する
+    tree.corrupt_order();
+    tree.insert(-1);
+}
+
+// == 出力テスト ==
+#[test]
+fn test_display() {
+    let mut tree = MultiAVL::new();
+    assert_eq!(tree.to_string(), "{}");
+    tree.insert_n(2, 1);
+    tree.insert_n(1, 3);
+    assert_eq!(tree.to_string(), "{1×3, 2×1}");
+
+    let mut tree = MultiAVL::new();
+    tree.insert("a\"b");
+    assert_eq!(tree.to_string(), "{\"a\\\"b\"×1}");
+}
+
+#[test]
+fn test_to_ascii() {
+    let tree: MultiAVL<i32> = MultiAVL::new();
+    assert_eq!(tree.to_ascii(), "(empty)\n");
+
+    let tree = setup_tree(&vec![2, 1, 4, 3, 1]);
+    let expected = "\
+2×1 (height 2, balance -1)
+├── L: 1×2 (height 0, balance 0)
+└── R: 4×1 (height 1, balance 1)
+    └── L: 3×1 (height 0, balance 0)
+";
+    assert_eq!(tree.to_ascii(), expected);
+}
+
+#[test]
+fn test_to_dot() {
+    let tree = setup_tree(&vec![2, 1, 3, 3]);
+    let expected = "\
+digraph MultiAVL {
+    node [shape=box];
+    n0 [label=\"2\\ncount: 1\\nheight: 1\\nbalance: 0\"];
+    n1 [label=\"1\\ncount: 1\\nheight: 0\\nbalance: 0\"];
+    n0 -> n1 [label=\"L\"];
+    n2 [label=\"3\\ncount: 2\\nheight: 0\\nbalance: 0\"];
+    n0 -> n2 [label=\"R\"];
+}
+";
+    assert_eq!(tree.to_dot(), expected);
+
+    let mut tree = MultiAVL::new();
+    tree.insert(String::from("say \"hi\""));
+    assert!(tree.to_dot().contains(r#"n0 [label="\"say \\\"hi\\\"\"\ncount: 1"#));
+}
+
+// == 操作の記録テスト ==
+#[cfg(feature = "trace")]
+#[test]
+fn test_trace_rotation() {
+    use crate::avl::{TraceEvent, TraceSide, to_json_lines};
+
+    let mut tree = MultiAVL::new();
+    tree.start_trace();
+    tree.insert(1);
+    tree.insert(2);
+    assert_eq!(tree.take_trace(), vec![
+        TraceEvent::Root { node: Some(1) },
+        TraceEvent::Link { parent: 1, child: 2, side: TraceSide::Right },
+    ]);
+
+    tree.insert(3);
+    let events = tree.stop_trace();
+    assert_eq!(events, vec![
+        TraceEvent::Link { parent: 2, child: 3, side: TraceSide::Right },
+        TraceEvent::RotateLeft { node: 1 },
+        TraceEvent::Unlink { parent: 1, child: 2, side: TraceSide::Right },
+        TraceEvent::Root { node: Some(2) },
+        TraceEvent::Link { parent: 2, child: 1, side: TraceSide::Left },
+    ]);
+    assert_eq!(to_json_lines(&events[..2]), "\
+{\"event\":\"link\",\"parent\":\"2\",\"child\":\"3\",\"side\":\"right\"}
+{\"event\":\"rotate_left\",\"node\":\"1\"}
+");
+
+    tree.insert(4);
+    assert!(tree.take_trace().is_empty());
+}
+
+#[cfg(feature = "trace")]
+#[test]
+fn test_trace_double_rotation_and_swap() {
+    use crate::avl::{TraceEvent, DoubleRotationKind};
+
+    let mut tree = setup_tree(&vec![3, 1]);
+    tree.start_trace();
+    tree.insert(2);
+    let events = tree.take_trace();
+    assert_eq!(events[1], TraceEvent::DoubleRotation { node: 3, kind: DoubleRotationKind::LeftRight });
+    assert_eq!(events[2], TraceEvent::RotateLeft { node: 1 });
+    assert!(events.contains(&TraceEvent::RotateRight { node: 3 }));
+
+    tree.erase(&2);
+    let events = tree.stop_trace();
+    assert_eq!(events[0], TraceEvent::Swap { node: 2, predecessor: 1 });
+    assert_eq!(TraceEvent::Root { node: None::<String> }.to_json(), "{\"event\":\"root\",\"node\":null}");
+}
+
+// == 統計テスト ==
+#[test]
+fn test_stats() {
+    let mut tree = MultiAVL::new();
+    let stats = tree.stats();
+    assert_eq!((stats.len, stats.distinct, stats.height), (0, 0, 0));
+    assert_eq!(stats.comparisons_per_search(), 0.0);
+
+    // 昇順の挿入では１回の単回転で済む
+    for i in 0..3 {
+        tree.insert(i);
+    }
+    tree.insert(1);
+    let stats = tree.stats();
+    assert_eq!((stats.len, stats.distinct, stats.height), (4, 3, 2));
+    assert_eq!(stats.average_depth, 2.0 / 3.0);
+    assert_eq!((stats.single_rotations, stats.double_rotations), (1, 0));
+    assert_eq!((stats.inserts, stats.erases, stats.searches), (4, 0, 4));
+    assert_eq!(stats.comparisons, 1 + 2 + 1);
+    assert!(stats.heap_bytes >= 3 * std::mem::size_of::<i32>());
+
+    tree.reset_stats();
+    tree.insert(-2);
+    tree.insert(-1);
+    tree.erase(&5);
+    let stats = tree.stats();
+    assert_eq!((stats.single_rotations, stats.double_rotations), (0, 1));
+    assert_eq!((stats.inserts, stats.erases, stats.searches), (2, 0, 3));
+    assert_eq!(stats.comparisons_per_search(), (2 + 3 + 2) as f64 / 3.0);
+}
+
+// rayon機能ではRefCellの代わりにRwLockを使い、rank機能では部分木の要素数を持つので大きさが変わる
+#[cfg(all(target_pointer_width = "64", not(feature = "rayon"), not(feature = "rank")))]
+#[test]
+fn test_node_size() {
+    // Rcのヘッダ16バイト + RefCell 8バイト + 子と親のポインタ24バイト + 値とバランス係数・個数
+    let mut tree = MultiAVL::new();
+    tree.insert(1i32);
+    assert_eq!(tree.stats().heap_bytes, 56);
+
+    let mut tree = MultiAVL::new();
+    tree.insert(1u64);
+    assert_eq!(tree.stats().heap_bytes, 64);
+}
+
+#[test]
+fn test_stats_height() {
+    let n = 1 << 12;
+    let tree = setup_tree(&(0..n - 1).collect());
+    let stats = tree.stats();
+    assert_eq!(stats.height, 12);
+    assert!(stats.average_depth < 11.0);
+    assert_eq!(stats.distinct, (n - 1) as usize);
+
+    // 出力のheightは葉を0と数えるので1小さい
+    let tree = setup_tree(&vec![2, 1, 4, 3, 1]);
+    assert!(tree.to_ascii().starts_with("2×1 (height 2,"));
+    assert_eq!(tree.stats().height, 3);
+}
+
+// == 値ごとのイテレータテスト ==
+#[test]
+fn test_iter_counts_rev() {
+    let mut tree = MultiAVL::new();
+    assert_eq!(tree.iter_counts().next_back(), None);
+    assert_eq!(tree.distinct_len(), 0);
+
+    let n = 1_000;
+    let mut nums: Vec<i32> = (0..n).collect();
+    let mut rng = StdRng::seed_from_u64(0);
+    nums.shuffle(&mut rng);
+    for i in &nums {
+        tree.insert_n(*i, (*i % 5 + 1) as usize);
+    }
+    assert_eq!(tree.distinct_len(), n as usize);
+
+    let expected: Vec<(i32, usize)> = (0..n).rev().map(|i| (i, (i % 5 + 1) as usize)).collect();
+    assert_eq!(tree.iter_counts().rev().collect::<Vec<_>>(), expected);
+
+    // 両端から進めても同じノードを２回返さない
+    let mut iter = tree.iter_counts();
+    let mut seen = vec![];
+    loop {
+        match (iter.next(), iter.next_back()) {
+            (Some(a), Some(b)) => { seen.push(a.0); seen.push(b.0); },
+            (Some(a), None) | (None, Some(a)) => seen.push(a.0),
+            (None, None) => break,
+        }
+    }
+    seen.sort();
+    assert_eq!(seen, (0..n).collect::<Vec<_>>());
+
+    for i in &nums[..n as usize / 2] {
+        tree.erase_n(i, usize::MAX);
+    }
+    assert_eq!(tree.distinct_len(), n as usize / 2);
+    assert!(tree.check_consistent().is_ok());
+}
+
+#[test]
+fn test_range_counts() {
+    let mut tree = MultiAVL::new();
+    for i in 0..100 {
+        tree.insert_n(i * 2, 3);
+    }
+
+    let values = |v: Vec<(i32, usize)>| v.into_iter().map(|(v, _)| v).collect::<Vec<_>>();
+    assert_eq!(values(tree.range_counts(10..16).collect()), vec![10, 12, 14]);
+    assert_eq!(values(tree.range_counts(9..=16).collect()), vec![10, 12, 14, 16]);
+    assert_eq!(values(tree.range_counts((Bound::Excluded(10), Bound::Included(14))).collect()), vec![12, 14]);
+    assert_eq!(values(tree.range_counts(..4).collect()), vec![0, 2]);
+    assert_eq!(values(tree.range_counts(195..).collect()), vec![196, 198]);
+    assert_eq!(values(tree.range_counts(190..).rev().collect()), vec![198, 196, 194, 192, 190]);
+    assert_eq!(tree.range_counts(11..12).next(), None);
+    assert_eq!(tree.range_counts((Bound::Included(20), Bound::Excluded(10))).next(), None);
+    assert_eq!(tree.range_counts(500..).next(), None);
+    assert_eq!(tree.range_counts(..).count(), 100);
+    assert_eq!(tree.range_counts(10..=10).collect::<Vec<_>>(), vec![(10, 3)]);
+}
+
+// == 平衡化の方法ごとのテスト ==
+// 同じ操作を各BalancePolicyで行い、BTreeMapと結果を比べる
+fn check_random_operations<P: BalancePolicy>() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut tree = MultiAVL::<i32, P>::with_policy();
+    let mut expected = BTreeMap::new();
+
+    for step in 0..3_000 {
+        let value = rng.gen_range(0..200);
+        match rng.gen_range(0..4) {
+            0 | 1 => {
+                tree.insert(value);
+                *expected.entry(value).or_insert(0) += 1;
+            },
+            2 => {
+                tree.erase(&value);
+                if let Some(count) = expected.get_mut(&value) {
+                    *count -= 1;
+                    if *count == 0 {
+                        expected.remove(&value);
+                    }
+                }
+            },
+            _ => {
+                let removed = expected.remove(&value).unwrap_or(0);
+                assert_eq!(tree.erase_n(&value, usize::MAX), removed);
+            },
+        }
+
+        if step % 100 == 0 {
+            assert!(tree.check_consistent().is_ok());
+            assert_eq!(tree.iter_counts().collect::<BTreeMap<_, _>>(), expected);
+        }
+        assert_eq!(tree.size(), expected.values().sum::<usize>());
+        assert_eq!(tree.floor(&value), expected.range(..=value).next_back().map(|(v, _)| *v));
+        assert_eq!(tree.successor(&value), expected.range(value + 1..).next().map(|(v, _)| *v));
+    }
+    assert!(tree.check_consistent().is_ok());
+}
+
+fn check_sorted_operations<P: BalancePolicy>() {
+    let n = 1 << 10;
+    let mut tree = MultiAVL::<i32, P>::with_policy();
+    for i in 0..n {
+        tree.insert(i);
+        assert!(tree.check_consistent().is_ok());
+    }
+    // どの方法でも高さは2log(n)以下に収まる
+    assert!(tree.stats().height <= 2 * 10 + 1);
+    assert_eq!(tree.min_value(), Some(0));
+    assert_eq!(tree.max_value(), Some(n - 1));
+
+    for i in (0..n).rev().step_by(2) {
+        tree.erase(&i);
+        assert!(tree.check_consistent().is_ok());
+    }
+    for i in (0..n).step_by(2) {
+        tree.erase(&i);
+        assert!(tree.check_consistent().is_ok());
+    }
+    assert!(tree.is_empty());
+    assert_eq!(tree.to_ascii(), "(empty)\n");
+}
+
+fn check_rollback_and_entry<P: BalancePolicy>() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut nums: Vec<i32> = (0..500).map(|i| i % 50).collect();
+    nums.shuffle(&mut rng);
+    let mut tree = MultiAVL::<i32, P>::with_policy();
+    for i in &nums {
+        tree.insert(*i);
+    }
+    let before: Vec<_> = tree.iter_counts().collect();
+
+    let checkpoint = tree.checkpoint();
+    for i in &nums[..250] {
+        tree.erase(i);
+        tree.insert_n(*i + 100, 3);
+        if let Entry::Occupied(entry) = tree.entry(*i) {
+            entry.remove_entry();
+        }
+    }
+    assert!(tree.check_consistent().is_ok());
+
+    tree.rollback(checkpoint);
+    assert!(tree.check_consistent().is_ok());
+    assert_eq!(tree.iter_counts().collect::<Vec<_>>(), before);
+}
+
+fn check_from_sorted_iter<P: BalancePolicy>() {
+    for n in [0, 1, 2, 3, 7, 8, 100, 1000] {
+        let values: Vec<i32> = (0..n).flat_map(|i| vec![i; (i % 3 + 1) as usize]).collect();
+        let mut tree = MultiAVL::<i32, P>::from_sorted_iter(values.clone());
+        assert!(tree.check_consistent().is_ok());
+        assert_eq!(tree.size(), values.len());
+        assert_eq!(tree.distinct_len(), n as usize);
+        assert_eq!(tree.iter().collect::<Vec<_>>(), values);
+
+        // 作った木に続けて操作できる
+        for i in 0..n {
+            tree.erase(&i);
+            tree.insert(-i);
+        }
+        assert!(tree.check_consistent().is_ok());
+    }
+}
+
+fn check_policy<P: BalancePolicy>() {
+    check_random_operations::<P>();
+    check_sorted_operations::<P>();
+    check_rollback_and_entry::<P>();
+    check_from_sorted_iter::<P>();
+}
+
+#[test]
+fn test_policy_avl() {
+    check_policy::<Avl>();
+}
+
+#[test]
+fn test_policy_red_black() {
+    check_policy::<RedBlack>();
+}
+
+#[test]
+fn test_red_black_shape() {
+    let mut tree = MultiAVL::<i32, RedBlack>::default();
+    for i in 0..4 {
+        tree.insert(i);
+    }
+    tree.insert(3);
+    let expected = "\
+1×1 (height 2, color black)
+├── L: 0×1 (height 0, color black)
+└── R: 2×1 (height 1, color black)
+    └── R: 3×2 (height 0, color red)
+";
+    assert_eq!(tree.to_ascii(), expected);
+    // AVL木と違って二重回転は数えない
+    assert_eq!((tree.stats().single_rotations, tree.stats().double_rotations), (1, 0));
+
+    // 根を赤にする
+    tree.corrupt_root_balance_bits(1);
+    assert_eq!(tree.validate(), Err(ValidationError::Color { value: 1 }));
+}
+
+#[test]
+#[should_panic(expected = "not sorted")]
+fn test_from_sorted_iter_unsorted() {
+    MultiAVL::<i32>::from_sorted_iter(vec![1, 2, 2, 1]);
+}
+
+// == 区間木テスト ==
+fn check_interval<P: BalancePolicy>() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut tree = IntervalMultiAVL::<i32, P>::with_policy();
+    let mut expected: BTreeMap<(i32, i32), usize> = BTreeMap::new();
+    for _ in 0..3_000 {
+        let start = rng.gen_range(0..200);
+        let range = start..start + rng.gen_range(1..30);
+        if rng.gen_bool(0.7) {
+            tree.insert(range.clone());
+            *expected.entry((range.start, range.end)).or_insert(0) += 1;
+        } else if let Some(((s, e), _)) = expected.range((start, 0)..).next() {
+            let range = *s..*e;
+            tree.erase(&range);
+            let count = expected.get_mut(&(range.start, range.end)).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                expected.remove(&(range.start, range.end));
+            }
+        }
+        assert!(tree.check_consistent().is_ok());
+
+        let a = rng.gen_range(0..230);
+        let b = a + rng.gen_range(0..20);
+        let actual = tree.overlapping(a..b);
+        let brute: Vec<_> = expected.iter()
+            .filter(|((s, e), _)| a < b && *s < b && *e > a)
+            .map(|((s, e), c)| (*s..*e, *c))
+            .collect();
+        assert_eq!(actual, brute);
+
+        let actual = tree.stabbing(&a);
+        let brute: Vec<_> = expected.iter()
+            .filter(|((s, e), _)| *s <= a && a < *e)
+            .map(|((s, e), c)| (*s..*e, *c))
+            .collect();
+        assert_eq!(actual, brute);
+    }
+    assert_eq!(tree.size(), expected.values().sum::<usize>());
+    assert_eq!(tree.distinct_len(), expected.len());
+}
+
+#[test]
+fn test_interval() {
+    check_interval::<Avl>();
+    check_interval::<RedBlack>();
+}
+
+#[test]
+fn test_interval_counts() {
+    let mut tree = IntervalMultiAVL::new();
+    tree.insert(0..10);
+    tree.insert(0..10);
+    tree.insert(5..6);
+    tree.insert(10..20);
+    assert_eq!(tree.count(&(0..10)), 2);
+    assert_eq!(tree.size(), 4);
+
+    // 半開区間なので端点だけが接する区間は重ならない
+    assert_eq!(tree.overlapping(10..11), vec![(10..20, 1)]);
+    assert_eq!(tree.overlapping(5..10), vec![(0..10, 2), (5..6, 1)]);
+    assert_eq!(tree.overlapping(3..3), vec![]);
+    assert_eq!(tree.stabbing(&5), vec![(0..10, 2), (5..6, 1)]);
+    assert_eq!(tree.stabbing(&20), vec![]);
+
+    tree.erase(&(0..10));
+    assert_eq!(tree.stabbing(&0), vec![(0..10, 1)]);
+    tree.erase(&(0..10));
+    assert!(!tree.contains(&(0..10)));
+    assert_eq!(tree.iter_counts().collect::<Vec<_>>(), vec![(5..6, 1), (10..20, 1)]);
+}
+
+#[test]
+#[should_panic]
+fn test_interval_empty() {
+    IntervalMultiAVL::new().insert(3..3);
+}
+
+// == 重み付きテスト ==
+fn check_weighted<P: BalancePolicy>() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut tree = WeightedMultiAVL::<i32, P>::with_policy();
+    // 値 -> (重み, 個数)
+    let mut expected: BTreeMap<i32, (u64, usize)> = BTreeMap::new();
+    for _ in 0..3_000 {
+        let value = rng.gen_range(0..100);
+        match rng.gen_range(0..10) {
+            0..=5 => {
+                let weight = rng.gen_range(0..10);
+                tree.insert(value, weight);
+                let e = expected.entry(value).or_insert((0, 0));
+                *e = (weight, e.1 + 1);
+            },
+            6 => {
+                let weight = rng.gen_range(0..10);
+                assert_eq!(tree.set_weight(&value, weight), expected.contains_key(&value));
+                if let Some(e) = expected.get_mut(&value) {
+                    e.0 = weight;
+                }
+            },
+            _ => {
+                tree.erase(&value);
+                if let Some(e) = expected.get_mut(&value) {
+                    e.1 -= 1;
+                    if e.1 == 0 {
+                        expected.remove(&value);
+                    }
+                }
+            },
+        }
+        assert!(tree.check_consistent().is_ok());
+
+        let total: u64 = expected.values().map(|(w, c)| w * *c as u64).sum();
+        assert_eq!(tree.total_weight(), total);
+        let prefix: u64 = expected.range(..value).map(|(_, (w, c))| w * *c as u64).sum();
+        assert_eq!(tree.weight_prefix(&value), prefix);
+        assert_eq!(tree.weight(&value), expected.get(&value).map(|v| v.0));
+
+        let w = rng.gen_range(0..total + 2);
+        let mut acc = 0;
+        let brute = expected.iter().find(|(_, (weight, c))| {
+            acc += weight * *c as u64;
+            w < acc
+        }).map(|(v, _)| *v);
+        assert_eq!(tree.find_by_weight(w), brute);
+    }
+    assert_eq!(tree.iter_counts().collect::<Vec<_>>(), expected.iter().map(|(v, (_, c))| (*v, *c)).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_weighted() {
+    check_weighted::<Avl>();
+    check_weighted::<RedBlack>();
+}
+
+#[test]
+fn test_weighted_find() {
+    let mut tree = WeightedMultiAVL::new();
+    tree.insert("a", 3);
+    tree.insert("b", 0);
+    tree.insert("c", 2);
+    tree.insert("c", 1);
+    // a a a c c
+    assert_eq!(tree.total_weight(), 5);
+    assert_eq!(tree.weight_prefix(&"c"), 3);
+    assert_eq!((0..6).map(|w| tree.find_by_weight(w)).collect::<Vec<_>>(),
+        vec![Some("a"), Some("a"), Some("a"), Some("c"), Some("c"), None]);
+}
+
+#[test]
+fn test_weighted_borrow() {
+    let mut tree = WeightedMultiAVL::new();
+    tree.insert(String::from("a"), 3);
+    tree.insert(String::from("b"), 2);
+    tree.insert(String::from("b"), 2);
+    assert!(tree.contains("a"));
+    assert_eq!(tree.count("b"), 2);
+    assert_eq!(tree.weight("b"), Some(2));
+    assert_eq!(tree.weight_prefix("b"), 3);
+    assert!(tree.set_weight("a", 1));
+    assert!(tree.erase("b"));
+    assert!(!tree.erase("c"));
+    assert_eq!(tree.total_weight(), 3);
+    assert!(tree.check_consistent().is_ok());
+}
+
+#[test]
+#[should_panic(expected = "WeightedMultiAVL::insert: total weight overflow")]
+fn test_weighted_overflow() {
+    let mut tree = WeightedMultiAVL::new();
+    tree.insert(1, u64::MAX / 2 + 1);
+    tree.insert(2, u64::MAX / 2 + 1);
+}
+
+#[test]
+fn test_weighted_overflow_unchanged() {
+    let mut tree = WeightedMultiAVL::new();
+    tree.insert(1, u64::MAX / 2 + 1);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| tree.insert(2, u64::MAX / 2 + 1)));
+    assert!(result.is_err());
+    assert!(!tree.contains(&2));
+    assert_eq!(tree.total_weight(), u64::MAX / 2 + 1);
+    assert!(tree.check_consistent().is_ok());
+}
+
+#[cfg(feature = "rand")]
+#[test]
+fn test_weighted_sample() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut tree = WeightedMultiAVL::new();
+    assert_eq!(tree.sample(&mut rng), None);
+    tree.insert(0, 1);
+    tree.insert(1, 3);
+    tree.insert(2, 0);
+    let mut hits = [0; 3];
+    for _ in 0..10_000 {
+        hits[tree.sample(&mut rng).unwrap()] += 1;
+    }
+    assert_eq!(hits[2], 0);
+    assert!((2_300..2_700).contains(&hits[0]));
+}
+
+// == update / modify テスト ==
+fn check_update<P: BalancePolicy>() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut tree = MultiAVL::<i32, P>::with_policy();
+    let mut expected: BTreeMap<i32, usize> = BTreeMap::new();
+    for _ in 0..500 {
+        let value = rng.gen_range(0..100);
+        tree.insert(value);
+        *expected.entry(value).or_insert(0) += 1;
+    }
+
+    let remove = |expected: &mut BTreeMap<i32, usize>, value: i32| {
+        let c = expected.get_mut(&value).unwrap();
+        *c -= 1;
+        if *c == 0 {
+            expected.remove(&value);
+        }
+    };
+    for i in 0..3_000 {
+        let old = rng.gen_range(0..110);
+        // 近くの値に変えて、その場で書き換える場合も通す
+        let new = if rng.gen_bool(0.5) { old + rng.gen_range(-2..=2) } else { rng.gen_range(0..100) };
+        let exists = expected.contains_key(&old);
+        let updated = if i % 2 == 0 {
+            tree.update(&old, new)
+        } else {
+            tree.modify(&old, |v| *v = new)
+        };
+        assert_eq!(updated, exists);
+        if exists {
+            remove(&mut expected, old);
+            *expected.entry(new).or_insert(0) += 1;
+        }
+        assert!(tree.check_consistent().is_ok());
+    }
+    assert_eq!(tree.iter_counts().collect::<BTreeMap<_, _>>(), expected);
+    assert_eq!(tree.size(), expected.values().sum::<usize>());
+
+    // 取り消すと元に戻る
+    let before: Vec<_> = tree.iter_counts().collect();
+    let checkpoint = tree.checkpoint();
+    for v in 0..100 {
+        tree.update(&v, v * 3);
+    }
+    tree.rollback(checkpoint);
+    assert!(tree.check_consistent().is_ok());
+    assert_eq!(tree.iter_counts().collect::<Vec<_>>(), before);
+}
+
+#[test]
+fn test_update() {
+    check_update::<Avl>();
+    check_update::<RedBlack>();
+}
+
+#[test]
+fn test_update_in_place() {
+    let mut tree = MultiAVL::<i32>::from_sorted_iter([10, 20, 30, 30]);
+    // 前後の値の間に収まるならノードはそのまま
+    let mut iter = tree.min_iter().unwrap();
+    assert!(tree.update(&10, 15));
+    assert_eq!(iter.next(), Some(15));
+
+    // 動かしたノードを指していたイテレータは外れたまま
+    let iter = tree.min_iter().unwrap();
+    assert!(tree.modify(&15, |v| *v = 40));
+    assert_eq!(iter.count(), 0);
+    assert_eq!(tree.iter().collect::<Vec<_>>(), vec![20, 30, 30, 40]);
+    assert_eq!(tree.min_value(), Some(20));
+    assert_eq!(tree.max_value(), Some(40));
+
+    // 個数が2以上なら１つだけ移す 同じ値があればそこに合わせる
+    assert!(tree.update(&30, 20));
+    assert_eq!(tree.iter_counts().collect::<Vec<_>>(), vec![(20, 2), (30, 1), (40, 1)]);
+    assert!(!tree.update(&35, 1));
+    assert!(!tree.modify(&35, |_| unreachable!()));
+    assert!(tree.check_consistent().is_ok());
+}
+
+// == 容量付きテスト ==
+fn check_bounded<P: BalancePolicy>(keep: Keep) {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut tree = BoundedMultiAVL::<i32, P>::with_policy(100, keep);
+    let mut all = Vec::new();
+    for _ in 0..5_000 {
+        let value = rng.gen_range(0..1_000);
+        all.push(value);
+        let before: Vec<i32> = tree.iter().collect();
+        let evicted = tree.insert(value);
+
+        // 入れた値と元の要素から、残らなかった１つが返る
+        let mut expected = before.clone();
+        expected.push(value);
+        expected.sort();
+        let removed = if expected.len() <= 100 {
+            None
+        } else if keep == Keep::Largest {
+            Some( expected.remove(0) )
+        } else {
+            expected.pop()
+        };
+        assert_eq!(evicted, removed);
+        assert_eq!(tree.iter().collect::<Vec<_>>(), expected);
+    }
+    assert!(tree.check_consistent().is_ok());
+
+    all.sort();
+    if keep == Keep::Largest {
+        all.reverse();
+    }
+    all.truncate(100);
+    all.sort();
+    assert_eq!(tree.into_inner().iter().collect::<Vec<_>>(), all);
+}
+
+#[test]
+fn test_bounded() {
+    check_bounded::<Avl>(Keep::Largest);
+    check_bounded::<Avl>(Keep::Smallest);
+    check_bounded::<RedBlack>(Keep::Largest);
+}
+
+#[test]
+fn test_bounded_ties() {
+    let mut tree = BoundedMultiAVL::new(2, Keep::Largest);
+    assert_eq!(tree.insert(5), None);
+    assert_eq!(tree.insert(5), None);
+    // 最小と等しい値は入れない
+    assert_eq!(tree.insert(5), Some(5));
+    assert_eq!(tree.insert(7), Some(5));
+    assert_eq!(tree.count(&5), 1);
+    assert_eq!(tree.insert(3), Some(3));
+    tree.erase(&7);
+    assert_eq!(tree.insert(3), None);
+    assert_eq!(tree.iter().collect::<Vec<_>>(), vec![3, 5]);
+
+    let mut tree = BoundedMultiAVL::new(0, Keep::Smallest);
+    assert_eq!(tree.insert(1), Some(1));
+    assert!(tree.is_empty());
+}
+
+#[test]
+fn test_bounded_erase_borrowed() {
+    let mut tree = BoundedMultiAVL::new(2, Keep::Smallest);
+    tree.insert(String::from("b"));
+    tree.insert(String::from("a"));
+    assert!(tree.erase("b"));
+    assert!(!tree.erase("c"));
+    assert_eq!(tree.insert(String::from("c")), None);
+    assert_eq!(tree.iter().collect::<Vec<_>>(), ["a", "c"]);
+}
+
+// == 順位テスト ==
+#[cfg(feature = "rank")]
+mod rank {
+    use super::*;
+
+    fn check_rank<P: BalancePolicy>() {
+        let mut tree = MultiAVL::<i32, P>::with_policy();
+        assert_eq!(tree.nth(0), None);
+        assert_eq!(tree.rank(&0), 0);
+
+        let mut expected = Vec::new();
+        for i in 0..300 {
+            let value = (i * 37) % 50;
+            tree.insert(value);
+            expected.push(value);
+            if i % 3 == 0 {
+                let removed = (i * 11) % 50;
+                if tree.erase(&removed) {
+                    let pos = expected.iter().position(|v| *v == removed).unwrap();
+                    expected.remove(pos);
+                }
+            }
+        }
+        expected.sort();
+
+        for (i, v) in expected.iter().enumerate() {
+            assert_eq!(tree.nth(i), Some(*v));
+        }
+        assert_eq!(tree.nth(expected.len()), None);
+        for v in -1..52 {
+            assert_eq!(tree.rank(&v), expected.iter().filter(|w| **w < v).count());
+        }
+    }
+
+    #[test]
+    fn test_rank() {
+        check_rank::<Avl>();
+        check_rank::<RedBlack>();
+    }
+
+    #[test]
+    fn test_rank_borrow() {
+        let mut tree = MultiAVL::new();
+        tree.insert_n(String::from("b"), 2);
+        tree.insert(String::from("a"));
+        assert_eq!(tree.rank("b"), 1);
+        assert_eq!(tree.rank("c"), 3);
+        assert_eq!(tree.nth(2).as_deref(), Some("b"));
+    }
+}
+
+// == サンプリングテスト ==
+#[cfg(feature = "rand")]
+mod sample {
+    use super::*;
+
+    fn check_sample<P: BalancePolicy>() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut tree = MultiAVL::<i32, P>::with_policy();
+        assert_eq!(tree.sample(&mut rng), None);
+        assert!(tree.sample_multiple(&mut rng, 3).is_empty());
+
+        let mut expected = BTreeMap::new();
+        for _ in 0..2_000 {
+            let value = rng.gen_range(0..100);
+            if rng.gen_bool(0.7) {
+                tree.insert(value);
+                *expected.entry(value).or_insert(0) += 1;
+            } else if expected.contains_key(&value) {
+                tree.erase(&value);
+                let c = expected.get_mut(&value).unwrap();
+                *c -= 1;
+                if *c == 0 {
+                    expected.remove(&value);
+                }
+            }
+        }
+        assert!(tree.check_consistent().is_ok());
+
+        // 取り消しや一括での構築でも部分木の要素数が保たれる
+        let checkpoint = tree.checkpoint();
+        for v in 0..50 {
+            tree.insert(v);
+            tree.erase(&(v * 2));
+        }
+        tree.rollback(checkpoint);
+        assert!(tree.check_consistent().is_ok());
+        let built = MultiAVL::<i32, P>::from_sorted_iter(tree.iter());
+        assert!(built.check_consistent().is_ok());
+
+        let all: Vec<i32> = tree.iter().collect();
+        assert_eq!(tree.sample_multiple(&mut rng, tree.size() + 1), all);
+        let sampled = tree.sample_multiple(&mut rng, 100);
+        assert_eq!(sampled.len(), 100);
+        assert!(sampled.windows(2).all(|w| w[0] <= w[1]));
+        assert!(sampled.iter().all(|v| sampled.iter().filter(|w| *w == v).count() <= expected[v]));
+
+        let distinct = built.sample_distinct(&mut rng, 10);
+        assert_eq!(distinct.len(), 10);
+        assert!(distinct.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(built.sample_distinct(&mut rng, usize::MAX), expected.keys().copied().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_sample() {
+        check_sample::<Avl>();
+        check_sample::<RedBlack>();
+    }
+
+    #[test]
+    fn test_sample_uniform() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut tree = MultiAVL::new();
+        tree.insert_n(0, 3);
+        tree.insert(1);
+
+        // 要素は個数に比例して、値は個数に関係なく選ばれる
+        let mut hits = [0; 2];
+        let mut distinct_hits = [0; 2];
+        for _ in 0..8_000 {
+            hits[tree.sample(&mut rng).unwrap()] += 1;
+            distinct_hits[tree.sample_distinct(&mut rng, 1)[0]] += 1;
+        }
+        assert!((5_800..6_200).contains(&hits[0]));
+        assert!((3_800..4_200).contains(&distinct_hits[0]));
+    }
+}
+
+// == 並列処理テスト ==
+#[cfg(feature = "rayon")]
+mod parallel {
+    use super::*;
+    use rayon::prelude::*;
+
+    fn random_tree<P: BalancePolicy>(seed: u64, n: usize) -> (MultiAVL<i32, P>, BTreeMap<i32, usize>) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut tree = MultiAVL::with_policy();
+        let mut expected = BTreeMap::new();
+        for _ in 0..n {
+            let value = rng.gen_range(0..n as i32);
+            tree.insert(value);
+            *expected.entry(value).or_insert(0) += 1;
+        }
+        (tree, expected)
+    }
+
+    #[test]
+    fn test_par_iter() {
+        let (tree, _) = random_tree::<Avl>(0, 5_000);
+        assert_eq!(tree.par_iter().collect::<Vec<_>>(), tree.iter().collect::<Vec<_>>());
+        assert_eq!(tree.par_iter().map(|v| v as i64).sum::<i64>(), tree.iter().map(|v| v as i64).sum::<i64>());
+        assert_eq!(tree.par_iter_counts().collect::<Vec<_>>(), tree.iter_counts().collect::<Vec<_>>());
+        assert_eq!(tree.par_iter_counts().find_any(|(v, _)| *v == -1), None);
+
+        let empty = MultiAVL::<i32>::new();
+        assert_eq!(empty.par_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_par_from_sorted_iter() {
+        let values: Vec<i32> = (0..50_000).map(|i| i / 3).collect();
+        let tree = MultiAVL::<i32>::par_from_sorted_iter(values.clone());
+        assert!(tree.check_consistent().is_ok());
+        assert_eq!(tree.iter().collect::<Vec<_>>(), values);
+
+        let tree = MultiAVL::<i32, RedBlack>::par_from_sorted_iter(values.clone());
+        assert!(tree.check_consistent().is_ok());
+        assert_eq!(tree.size(), values.len());
+    }
+
+    fn check_union_intersection<P: BalancePolicy>() {
+        let (a, expected_a) = random_tree::<P>(1, 6_000);
+        let (b, expected_b) = random_tree::<P>(2, 3_000);
+
+        let mut expected = expected_a.clone();
+        for (v, c) in &expected_b {
+            let count = expected.entry(*v).or_insert(0);
+            *count = (*count).max(*c);
+        }
+        let union = a.par_union(&b);
+        assert!(union.check_consistent().is_ok());
+        assert_eq!(union.iter_counts().collect::<BTreeMap<_, _>>(), expected);
+
+        let expected: BTreeMap<_, _> = expected_a.iter()
+            .filter_map(|(v, c)| expected_b.get(v).map(|d| (*v, *c.min(d))))
+            .collect();
+        let intersection = b.par_intersection(&a);
+        assert!(intersection.check_consistent().is_ok());
+        assert_eq!(intersection.iter_counts().collect::<BTreeMap<_, _>>(), expected);
+
+        assert!(a.par_intersection(&MultiAVL::with_policy()).is_empty());
+    }
+
+    #[test]
+    fn test_par_union_intersection() {
+        check_union_intersection::<Avl>();
+        check_union_intersection::<RedBlack>();
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<MultiAVL<i32>>();
+        assert_send_sync::<MultiAVL<String, RedBlack>>();
+    }
+}
+
+// == スナップショットテスト ==
+#[cfg(feature = "std")]
+mod snapshot {
+    use super::*;
+    use crate::avl::{SnapshotError, fix_checksum};
+
+    fn snapshot<T: Ord + Clone + crate::avl::SnapshotCodec>(tree: &MultiAVL<T>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        tree.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut tree = MultiAVL::new();
+        for _ in 0..10_000 {
+            tree.insert(rng.gen_range(-1_000..1_000i64));
+        }
+        tree.insert_n(i64::MIN, 1 << 31);
+        tree.insert(i64::MAX);
+
+        let bytes = snapshot(&tree);
+        let restored = MultiAVL::<i64>::read_from(bytes.as_slice()).unwrap();
+        assert!(restored.check_consistent().is_ok());
+        assert_eq!(restored.size(), tree.size());
+        assert_eq!(restored.iter_counts().collect::<Vec<_>>(), tree.iter_counts().collect::<Vec<_>>());
+
+        let restored = MultiAVL::<i64, RedBlack>::read_from(bytes.as_slice()).unwrap();
+        assert!(restored.check_consistent().is_ok());
+        assert_eq!(restored.distinct_len(), tree.distinct_len());
+
+        let empty = MultiAVL::<u8>::read_from(snapshot(&MultiAVL::<u8>::new()).as_slice()).unwrap();
+        assert!(empty.is_empty());
+
+        let mut strings = MultiAVL::new();
+        for s in ["b", "a", "ß", "", "a"] {
+            strings.insert(String::from(s));
+        }
+        let restored = MultiAVL::<String>::read_from(snapshot(&strings).as_slice()).unwrap();
+        assert_eq!(restored.iter().collect::<Vec<_>>(), ["", "a", "a", "b", "ß"]);
+    }
+
+    #[test]
+    fn test_snapshot_compact() {
+        // ヘッダ6バイト + 種類数1バイト + (値1バイト + 個数1バイト) * 3 + CRC4バイト
+        let tree = MultiAVL::from_sorted_iter([0u32, 1, 1, 127]);
+        assert_eq!(snapshot(&tree).len(), 6 + 1 + 2 * 3 + 4);
+    }
+
+    #[test]
+    fn test_snapshot_errors() {
+        let tree = MultiAVL::from_sorted_iter([1u32, 2, 2, 3]);
+        let bytes = snapshot(&tree);
+
+        let mut broken = bytes.clone();
+        broken[0] = b'X';
+        assert!(matches!(MultiAVL::<u32>::read_from(broken.as_slice()), Err(SnapshotError::BadMagic)));
+
+        let mut broken = bytes.clone();
+        broken[4] = 2;
+        assert!(matches!(MultiAVL::<u32>::read_from(broken.as_slice()), Err(SnapshotError::UnsupportedVersion(2))));
+
+        assert!(matches!(MultiAVL::<i32>::read_from(bytes.as_slice()), Err(SnapshotError::TypeMismatch { .. })));
+
+        // 途中で切れている
+        let result = MultiAVL::<u32>::read_from(&bytes[..bytes.len() - 1]);
+        assert!(matches!(result, Err(SnapshotError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof));
+
+        // 値を書き換えるとCRCが合わなくなる
+        let mut broken = bytes.clone();
+        broken[9] = 5;
+        assert!(matches!(MultiAVL::<u32>::read_from(broken.as_slice()), Err(SnapshotError::ChecksumMismatch { .. })));
+
+        // CRCが合っていても昇順でなければ読み込まない
+        let mut broken = bytes.clone();
+        broken[11] = 1;
+        fix_checksum(&mut broken);
+        assert!(matches!(MultiAVL::<u32>::read_from(broken.as_slice()), Err(SnapshotError::Unsorted { index: 2 })));
+
+        let mut broken = bytes.clone();
+        broken[10] = 0;
+        fix_checksum(&mut broken);
+        assert!(matches!(MultiAVL::<u32>::read_from(broken.as_slice()), Err(SnapshotError::Corrupt(_))));
+    }
+}
+
+// == 永続化テスト ==
+#[cfg(feature = "std")]
+mod durable {
+    use super::*;
+    use crate::avl::DurableMultiAVL;
+    use std::path::PathBuf;
+
+    // テストごとに別の一時ディレクトリを使い、終わったら消す
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = std::env::temp_dir().join(format!("multi_avl_{}_{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn file_names(dir: &TempDir) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(&dir.0).unwrap()
+            .map(|v| v.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_durable_reopen() {
+        let dir = TempDir::new("reopen");
+        let mut expected = BTreeMap::new();
+        {
+            let mut tree = DurableMultiAVL::<i64>::open(&dir.0).unwrap();
+            tree.set_compact_threshold(100);
+            let mut rng = StdRng::seed_from_u64(0);
+            for _ in 0..1_000 {
+                let value = rng.gen_range(0..50);
+                if rng.gen_bool(0.6) {
+                    tree.insert(value).unwrap();
+                    *expected.entry(value).or_insert(0) += 1;
+                } else {
+                    tree.erase(&value).unwrap();
+                    if let Some(c) = expected.get_mut(&value) {
+                        *c -= 1;
+                        if *c == 0 { expected.remove(&value); }
+                    }
+                }
+            }
+            tree.sync().unwrap();
+            assert_eq!(tree.iter_counts().collect::<BTreeMap<_, _>>(), expected);
+        }
+        // 古い世代は消えている
+        let names = file_names(&dir);
+        assert_eq!(names.len(), 2);
+        assert!(names[0].starts_with("snapshot.") && names[1].starts_with("wal."));
+
+        let tree = DurableMultiAVL::<i64, RedBlack>::open(&dir.0).unwrap();
+        assert!(tree.check_consistent().is_ok());
+        assert_eq!(tree.iter_counts().collect::<BTreeMap<_, _>>(), expected);
+
+        let mut tree = tree;
+        tree.compact().unwrap();
+        drop(tree);
+        let tree = DurableMultiAVL::<i64>::open(&dir.0).unwrap().into_inner();
+        assert_eq!(tree.iter_counts().collect::<BTreeMap<_, _>>(), expected);
+
+        assert!(DurableMultiAVL::<String>::open(&dir.0).is_err());
+    }
+
+    #[test]
+    fn test_durable_truncated_log() {
+        let dir = TempDir::new("truncated");
+        let mut rng = StdRng::seed_from_u64(1);
+        // 各操作の後の木の状態
+        let mut states = vec![BTreeMap::new()];
+        {
+            let mut tree = DurableMultiAVL::<String>::open(&dir.0).unwrap();
+            tree.set_compact_threshold(0);
+            for _ in 0..200 {
+                let value = "x".repeat(rng.gen_range(0..20));
+                if rng.gen_bool(0.7) {
+                    tree.insert(value).unwrap();
+                } else {
+                    tree.erase(&value).unwrap();
+                }
+                let state: BTreeMap<String, usize> = tree.iter_counts().collect();
+                if state != *states.last().unwrap() {
+                    states.push(state);
+                }
+            }
+        }
+
+        let wal = dir.0.join("wal.0");
+        let bytes = std::fs::read(&wal).unwrap();
+        for _ in 0..100 {
+            let len = rng.gen_range(0..=bytes.len());
+            std::fs::write(&wal, &bytes[..len]).unwrap();
+
+            // 切れたところより前の操作がすべて適用された状態になる
+            let mut tree = DurableMultiAVL::<String>::open(&dir.0).unwrap();
+            assert!(tree.check_consistent().is_ok());
+            let state: BTreeMap<String, usize> = tree.iter_counts().collect();
+            let index = states.iter().position(|v| *v == state).unwrap();
+            if len == bytes.len() {
+                assert_eq!(index, states.len() - 1);
+            }
+
+            // 壊れた末尾は捨てられ、続けて書き込める
+            tree.insert(String::from("new")).unwrap();
+            drop(tree);
+            let tree = DurableMultiAVL::<String>::open(&dir.0).unwrap();
+            assert_eq!(tree.count("new"), 1);
+            assert_eq!(tree.size(), states[index].values().sum::<usize>() + 1);
+        }
+    }
+
+    #[test]
+    fn test_durable_truncated_header() {
+        let dir = TempDir::new("truncated_header");
+        {
+            let mut tree = DurableMultiAVL::<u32>::open(&dir.0).unwrap();
+            tree.insert(1).unwrap();
+        }
+        let wal = dir.0.join("wal.0");
+        let header = std::fs::read(&wal).unwrap()[..6].to_vec();
+
+        // ヘッダの途中で切れていても、開き直すたびに正しいヘッダから書き直される
+        for len in 0..header.len() {
+            std::fs::write(&wal, &header[..len]).unwrap();
+            let mut tree = DurableMultiAVL::<u32>::open(&dir.0).unwrap();
+            assert!(tree.is_empty());
+            tree.insert(2).unwrap();
+            drop(tree);
+            assert!(std::fs::read(&wal).unwrap().starts_with(&header));
+
+            let tree = DurableMultiAVL::<u32>::open(&dir.0).unwrap();
+            assert_eq!(tree.iter().collect::<Vec<_>>(), [2]);
+        }
+    }
+
+    #[test]
+    fn test_durable_erase_borrowed() {
+        let dir = TempDir::new("erase_borrowed");
+        {
+            let mut tree = DurableMultiAVL::<String>::open(&dir.0).unwrap();
+            tree.insert(String::from("a")).unwrap();
+            tree.insert(String::from("a")).unwrap();
+            assert!(tree.erase("a").unwrap());
+            assert!(!tree.erase("b").unwrap());
+        }
+        let tree = DurableMultiAVL::<String>::open(&dir.0).unwrap();
+        assert_eq!(tree.count("a"), 1);
+    }
+
+    #[test]
+    fn test_durable_failed_write() {
+        let dir = TempDir::new("failed_write");
+        {
+            let mut tree = DurableMultiAVL::<u32>::open(&dir.0).unwrap();
+            tree.insert(1).unwrap();
+            // レコードの途中まで書いたところで失敗しても木は変わらない
+            tree.fail_next_write(3);
+            assert!(tree.insert(2).is_err());
+            assert_eq!(tree.iter().collect::<Vec<_>>(), [1]);
+            tree.insert(3).unwrap();
+            assert!(tree.erase(&1).unwrap());
+        }
+        // 書きかけのレコードは切り捨てられているので、後のレコードも復元できる
+        let tree = DurableMultiAVL::<u32>::open(&dir.0).unwrap();
+        assert_eq!(tree.iter().collect::<Vec<_>>(), [3]);
+    }
+
+    #[test]
+    fn test_durable_failed_compaction() {
+        let dir = TempDir::new("failed_compaction");
+        let blocker = dir.0.join("snapshot.1.tmp");
+        {
+            let mut tree = DurableMultiAVL::<u32>::open(&dir.0).unwrap();
+            tree.set_compact_threshold(2);
+            tree.insert(1).unwrap();
+            // 一時ファイルの場所にディレクトリがあるとスナップショットを書けない
+            std::fs::create_dir(&blocker).unwrap();
+            // ログに書けた操作はコンパクションに失敗してもエラーにならない
+            assert_eq!(tree.insert(2).unwrap(), 1);
+            // 次の書き込みの前のコンパクションが失敗したら、その操作は行わない
+            assert!(tree.insert(3).is_err());
+            assert!(tree.erase(&1).is_err());
+            assert_eq!(tree.iter().collect::<Vec<_>>(), [1, 2]);
+
+            std::fs::remove_dir(&blocker).unwrap();
+            tree.insert(3).unwrap();
+        }
+        assert_eq!(file_names(&dir), ["snapshot.1", "wal.1"]);
+        let tree = DurableMultiAVL::<u32>::open(&dir.0).unwrap();
+        assert_eq!(tree.iter().collect::<Vec<_>>(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_durable_corrupt_record() {
+        let dir = TempDir::new("corrupt");
+        {
+            let mut tree = DurableMultiAVL::<u32>::open(&dir.0).unwrap();
+            for v in 0..10 {
+                tree.insert(v).unwrap();
+            }
+        }
+        // 6番目のレコードのCRCを壊すと、そこから後ろは捨てられる
+        let wal = dir.0.join("wal.0");
+        let mut bytes = std::fs::read(&wal).unwrap();
+        bytes[6 + 6 * 5 + 2] ^= 0xff;
+        std::fs::write(&wal, &bytes).unwrap();
+
+        let tree = DurableMultiAVL::<u32>::open(&dir.0).unwrap();
+        assert_eq!(tree.iter().collect::<Vec<_>>(), (0..5).collect::<Vec<_>>());
+    }
+}
+
+// == Pythonバインディングテスト ==
+#[cfg(feature = "python")]
+mod python {
+    use pyo3::prelude::*;
+    use pyo3::types::PyModule;
+
+    // 埋め込んだインタプリタにモジュールを登録してPythonのコードを実行する
+    fn run(code: &std::ffi::CStr) -> PyResult<()> {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "multi_avl")?;
+            crate::python::register(&module)?;
+            let globals = pyo3::types::PyDict::new(py);
+            globals.set_item("MultiAVL", module.getattr("MultiAVL")?)?;
+            py.run(code, Some(&globals), None)
+        })
+    }
+
+    #[test]
+    fn test_python_multiset() {
+        run(cr#"
+s = MultiAVL([3, 1, 3, 2])
+assert list(s) == [1, 2, 3, 3]
+assert s.add(3) == 3 and len(s) == 5 and s.count(3) == 3
+assert 2 in s and 4 not in s
+s.remove(3)
+assert s.kth(0) == 1 and s.kth(-1) == 3 and s.rank(3) == 2
+assert s.lower_bound(2) == 2 and s.upper_bound(2) == 3 and s.floor(0) is None
+assert s.min() == 1 and s.max() == 3
+"#).unwrap();
+    }
+
+    #[test]
+    fn test_python_compare_error() {
+        run(cr#"
+s = MultiAVL([1, 2])
+try:
+    s.add("x")
+    raise AssertionError("TypeError was not raised")
+except TypeError:
+    pass
+assert list(s) == [1, 2]
+"#).unwrap();
+    }
+}